@@ -0,0 +1,385 @@
+//! The `apply-changes` subcommand: merging OsmChange files into an OSM file
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use osm_parse::{
+    Action, Change, Element, FileFormats, OsmParseError, OsmReader, OsmTag, OsmWriter,
+};
+use structopt::StructOpt;
+
+use crate::{exit_unrecognised, exit_with_error, output::Output};
+
+/// Exit code when the base file is not sorted
+const EXIT_UNSORTED: i32 = 10;
+
+/// Apply OsmChange files to an OSM file
+///
+///    The base file must be sorted as OSM extracts are: nodes, then
+///    ways, then relations, each by id. The result is sorted the same
+///    way.
+///
+///    Changes are matched to elements by id. Of several changes to one
+///    element the highest version wins, or the last one for equal
+///    versions; a change older than the element in the base file is
+///    skipped. Deleted elements are left out of the result.
+///
+///    The base file is streamed; only the changes are kept in memory.
+///    An existing output file is only replaced once the result is
+///    complete.
+#[derive(StructOpt, Debug)]
+pub struct ApplyOptions {
+    /// Sorted OSM file to apply the changes to
+    #[structopt(parse(from_os_str))]
+    base: PathBuf,
+
+    /// OsmChange files, applied in order
+    #[structopt(parse(from_os_str), required = true)]
+    changes: Vec<PathBuf>,
+
+    /// Write the result to this file instead of standard output
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+/// Position of an element in a sorted file
+type Key = (OsmTag, i64);
+
+/// Why the base file could not be merged
+#[derive(Debug)]
+enum MergeError {
+    Parse(OsmParseError),
+    /// An element, and the one before it that should have come later
+    Unsorted(Key, Key),
+    /// Writing the output failed
+    Write(io::Error),
+}
+
+/// Number of changes applied per action, and skipped as outdated
+#[derive(Default)]
+struct Summary {
+    created: u64,
+    modified: u64,
+    deleted: u64,
+    outdated: u64,
+    /// Deletions of elements that are not in the base file
+    absent: u64,
+}
+
+/// Apply the changes and return the exit code
+pub fn run(options: &ApplyOptions) -> i32 {
+    if let Some(output) = &options.output {
+        if same_file(output, &options.base) {
+            let e = io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "would overwrite the base file",
+            );
+            exit_with_error(output, &e.into());
+        }
+    }
+
+    let mut changes = BTreeMap::new();
+    for path in &options.changes {
+        let mut reader = open_reader(path);
+        for change in reader.changes() {
+            match change {
+                Ok(change) => insert_change(&mut changes, change),
+                Err(e) => exit_with_error(path, &e),
+            }
+        }
+    }
+
+    let mut base = open_reader(&options.base);
+    let (output, writer) = Output::create(options.output.as_deref());
+    match output.finish(merge(&mut base, changes, writer)) {
+        Ok(summary) => {
+            eprintln!(
+                "{} created, {} modified, {} deleted, {} outdated changes skipped, \
+                 {} deletions of absent elements skipped",
+                summary.created,
+                summary.modified,
+                summary.deleted,
+                summary.outdated,
+                summary.absent
+            );
+            0
+        }
+        Err(MergeError::Parse(e)) => exit_with_error(&options.base, &e),
+        Err(MergeError::Write(e)) => {
+            let output = options.output.as_deref().unwrap_or(Path::new("-"));
+            exit_with_error(output, &e.into())
+        }
+        Err(MergeError::Unsorted(key, last)) => {
+            eprintln!(
+                "The base file is not sorted: {:?} {} after {:?} {}",
+                key.0, key.1, last.0, last.1
+            );
+            EXIT_UNSORTED
+        }
+    }
+}
+
+fn open_reader(path: &Path) -> OsmReader<Box<dyn BufRead>> {
+    match FileFormats::open(path) {
+        Ok(Some(reader)) => reader,
+        Ok(None) => exit_unrecognised(),
+        Err(e) => exit_with_error(path, &e.into()),
+    }
+}
+
+/// Whether `output` exists and is the same file as `base`
+fn same_file(output: &Path, base: &Path) -> bool {
+    match (fs::canonicalize(output), fs::canonicalize(base)) {
+        (Ok(output), Ok(base)) => output == base,
+        _ => false,
+    }
+}
+
+/// Keep `change` unless there already is a newer change to its element
+fn insert_change(changes: &mut BTreeMap<Key, Change>, change: Change) {
+    let key = (change.element.tag(), change.element.id());
+    match changes.get(&key) {
+        Some(existing) if version(&existing.element) > version(&change.element) => (),
+        _ => {
+            changes.insert(key, change);
+        }
+    }
+}
+
+fn version(element: &Element) -> u32 {
+    element.meta().version.unwrap_or(0)
+}
+
+/// Stream the sorted `base` to `output`, with `changes` applied
+fn merge<R: BufRead>(
+    base: &mut OsmReader<R>,
+    changes: BTreeMap<Key, Change>,
+    output: impl Write,
+) -> Result<Summary, MergeError> {
+    let mut writer = OsmWriter::new(output)?;
+    let mut summary = Summary::default();
+    let mut changes = changes.into_iter().peekable();
+    let mut last: Option<Key> = None;
+
+    for element in base.elements() {
+        let element = element?;
+        let key = (element.tag(), element.id());
+        if let Some(last) = last.filter(|&last| last >= key) {
+            return Err(MergeError::Unsorted(key, last));
+        }
+        last = Some(key);
+
+        // Changes to elements that are not in the base file
+        while let Some((_, change)) = changes.next_if(|(change_key, _)| *change_key < key) {
+            summary.apply(&mut writer, change, false)?;
+        }
+
+        match changes.next_if(|(change_key, _)| *change_key == key) {
+            Some((_, change)) if version(&change.element) >= version(&element) => {
+                summary.apply(&mut writer, change, true)?
+            }
+            Some(_) => {
+                summary.outdated += 1;
+                writer.write(&element)?;
+            }
+            None => writer.write(&element)?,
+        }
+    }
+
+    for (_, change) in changes {
+        summary.apply(&mut writer, change, false)?;
+    }
+    writer.finish()?;
+
+    Ok(summary)
+}
+
+impl Summary {
+    /// Write the element of `change`, unless it is deleted; `in_base`
+    /// tells whether the element is in the base file
+    fn apply(
+        &mut self,
+        writer: &mut OsmWriter<impl Write>,
+        change: Change,
+        in_base: bool,
+    ) -> io::Result<()> {
+        match change.action {
+            Some(Action::Delete) => {
+                match in_base {
+                    true => self.deleted += 1,
+                    false => self.absent += 1,
+                }
+                return Ok(());
+            }
+            Some(Action::Create) => self.created += 1,
+            // Elements outside of any block replace the old ones too
+            Some(Action::Modify) | None => self.modified += 1,
+        }
+
+        writer.write(&change.element)
+    }
+}
+
+impl From<OsmParseError> for MergeError {
+    fn from(e: OsmParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<io::Error> for MergeError {
+    fn from(e: io::Error) -> Self {
+        Self::Write(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tag, id and version of each element written
+    type Written = Vec<(OsmTag, i64, u32)>;
+
+    /// Merge the OsmChange `changes` into the `base` document
+    fn apply(base: &str, changes: &str) -> Result<(Summary, Written), MergeError> {
+        let mut change_map = BTreeMap::new();
+        for change in OsmReader::new(changes.as_bytes()).changes() {
+            insert_change(&mut change_map, change.unwrap());
+        }
+
+        let mut output = Vec::new();
+        let summary = merge(
+            &mut OsmReader::new(base.as_bytes()),
+            change_map,
+            &mut output,
+        )?;
+        let written = OsmReader::new(&output[..])
+            .elements()
+            .map(|element| {
+                let element = element.unwrap();
+                (element.tag(), element.id(), version(&element))
+            })
+            .collect();
+
+        Ok((summary, written))
+    }
+
+    const BASE: &str = r#"<osm>
+        <node id="1" version="1" lat="1" lon="1"/>
+        <node id="3" version="2" lat="1" lon="1"/>
+        <node id="5" version="1" lat="1" lon="1"/>
+        <way id="1" version="1"><nd ref="1"/><nd ref="3"/></way>
+        </osm>"#;
+
+    fn counts(summary: &Summary) -> [u64; 5] {
+        [
+            summary.created,
+            summary.modified,
+            summary.deleted,
+            summary.outdated,
+            summary.absent,
+        ]
+    }
+
+    #[test]
+    fn create_between_base_elements() {
+        let (summary, written) = apply(
+            BASE,
+            r#"<osmChange><create><node id="2" version="1" lat="2" lon="2"/></create></osmChange>"#,
+        )
+        .unwrap();
+
+        assert_eq!(counts(&summary), [1, 0, 0, 0, 0]);
+        assert_eq!(
+            written,
+            [
+                (OsmTag::Node, 1, 1),
+                (OsmTag::Node, 2, 1),
+                (OsmTag::Node, 3, 2),
+                (OsmTag::Node, 5, 1),
+                (OsmTag::Way, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn modify_and_outdated() {
+        let (summary, written) = apply(
+            BASE,
+            r#"<osmChange>
+            <modify><node id="1" version="2" lat="2" lon="2"/></modify>
+            <modify><node id="3" version="1" lat="2" lon="2"/></modify>
+            </osmChange>"#,
+        )
+        .unwrap();
+
+        assert_eq!(counts(&summary), [0, 1, 0, 1, 0]);
+        assert_eq!(written[..2], [(OsmTag::Node, 1, 2), (OsmTag::Node, 3, 2)]);
+    }
+
+    #[test]
+    fn delete() {
+        let (summary, written) = apply(
+            BASE,
+            r#"<osmChange><delete>
+            <node id="3" version="3"/>
+            <node id="4" version="1"/>
+            </delete></osmChange>"#,
+        )
+        .unwrap();
+
+        assert_eq!(counts(&summary), [0, 0, 1, 0, 1]);
+        assert_eq!(
+            written,
+            [
+                (OsmTag::Node, 1, 1),
+                (OsmTag::Node, 5, 1),
+                (OsmTag::Way, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_after_last_element() {
+        let (summary, written) = apply(
+            BASE,
+            r#"<osmChange>
+            <create><relation id="1" version="1"/></create>
+            <modify><way id="7" version="2"/></modify>
+            <delete><relation id="9" version="2"/></delete>
+            </osmChange>"#,
+        )
+        .unwrap();
+
+        assert_eq!(counts(&summary), [1, 1, 0, 0, 1]);
+        assert_eq!(
+            written[4..],
+            [(OsmTag::Way, 7, 2), (OsmTag::Relation, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn unsorted_base() {
+        let base = r#"<osm>
+            <node id="1" lat="1" lon="1"/>
+            <way id="1"/>
+            <node id="2" lat="1" lon="1"/>
+            </osm>"#;
+        match apply(base, "<osmChange/>") {
+            Err(MergeError::Unsorted(key, last)) => {
+                assert_eq!((key, last), ((OsmTag::Node, 2), (OsmTag::Way, 1)));
+            }
+            other => panic!("{:?}", other.map(|(_, written)| written)),
+        }
+
+        let duplicate =
+            r#"<osm><node id="1" lat="1" lon="1"/><node id="1" lat="1" lon="1"/></osm>"#;
+        assert!(matches!(
+            apply(duplicate, "<osmChange/>"),
+            Err(MergeError::Unsorted(..))
+        ));
+    }
+}
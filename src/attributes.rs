@@ -0,0 +1,246 @@
+//! Decoding and encoding of the attribute values found on OSM elements
+
+const NANO: i64 = 1_000_000_000;
+
+/// Parse a decimal degree value into fixed-point nanodegrees
+///
+/// Digits beyond the ninth decimal are truncated; values outside
+/// `-limit..=limit` degrees are rejected.
+pub(crate) fn parse_nanodegrees(value: &str, limit: i64) -> Option<i64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+
+    if int_part.is_empty() && frac_part.is_empty()
+        || !int_part.bytes().all(|b| b.is_ascii_digit())
+        || !frac_part.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let int = match int_part {
+        "" => 0,
+        _ => int_part.parse::<i64>().ok()?,
+    };
+    let frac = frac_part
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0, |acc, b| acc * 10 + i64::from(b - b'0'));
+
+    let nanodegrees = int.checked_mul(NANO)?.checked_add(frac)?;
+    if nanodegrees > limit * NANO {
+        return None;
+    }
+
+    Some(if negative { -nanodegrees } else { nanodegrees })
+}
+
+/// Parse an `YYYY-MM-DDTHH:MM:SSZ` timestamp into seconds since the Unix epoch
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let bytes = value.as_bytes();
+    if bytes.len() != 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes[10] != b'T'
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+        || bytes[19] != b'Z'
+    {
+        return None;
+    }
+
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let field = &value[range];
+        match field.bytes().all(|b| b.is_ascii_digit()) {
+            true => field.parse().ok(),
+            false => None,
+        }
+    };
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+/// Format fixed-point nanodegrees as decimal degrees, without trailing
+/// zeros
+pub(crate) fn format_nanodegrees(nanodegrees: i64) -> String {
+    let sign = if nanodegrees < 0 { "-" } else { "" };
+    let (int, frac) = (
+        nanodegrees.unsigned_abs() / NANO as u64,
+        nanodegrees.unsigned_abs() % NANO as u64,
+    );
+
+    match frac {
+        0 => format!("{}{}", sign, int),
+        _ => {
+            let frac = format!("{:09}", frac);
+            format!("{}{}.{}", sign, int, frac.trim_end_matches('0'))
+        }
+    }
+}
+
+/// Format seconds since the Unix epoch as an `YYYY-MM-DDTHH:MM:SSZ`
+/// timestamp
+pub(crate) fn format_timestamp(seconds: i64) -> String {
+    let (days, second_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3_600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+pub(crate) fn parse_visible(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Proleptic Gregorian date of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nanodegrees() {
+        let cases = [
+            ("51.5", 90, Some(51_500_000_000)),
+            ("-0.1", 90, Some(-100_000_000)),
+            ("+12", 90, Some(12_000_000_000)),
+            (".5", 90, Some(500_000_000)),
+            ("5.", 90, Some(5_000_000_000)),
+            ("-0", 90, Some(0)),
+            // Digits beyond the ninth decimal are truncated
+            ("1.1234567899", 90, Some(1_123_456_789)),
+            ("-1.0000000019", 90, Some(-1_000_000_001)),
+            ("90", 90, Some(90_000_000_000)),
+            ("-90", 90, Some(-90_000_000_000)),
+            ("90.000000001", 90, None),
+            ("-90.1", 90, None),
+            ("180", 180, Some(180_000_000_000)),
+            ("-180.000000001", 180, None),
+            ("", 90, None),
+            (".", 90, None),
+            ("-", 90, None),
+            ("1e5", 180, None),
+            ("1.2.3", 90, None),
+            (" 1", 90, None),
+            ("--1", 90, None),
+            ("99999999999999999999", 180, None),
+        ];
+        for (value, limit, expected) in cases {
+            assert_eq!(parse_nanodegrees(value, limit), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let cases = [
+            ("1970-01-01T00:00:00Z", Some(0)),
+            ("2021-03-04T05:06:07Z", Some(1_614_834_367)),
+            ("1969-12-31T23:59:59Z", Some(-1)),
+            ("2020-02-29T00:00:00Z", Some(1_582_934_400)),
+            ("2000-02-29T00:00:00Z", Some(951_782_400)),
+            ("2016-12-31T23:59:60Z", Some(1_483_228_800)),
+            ("2021-02-29T00:00:00Z", None),
+            ("1900-02-29T00:00:00Z", None),
+            ("2021-02-31T00:00:00Z", None),
+            ("2021-04-31T00:00:00Z", None),
+            ("2021-00-01T00:00:00Z", None),
+            ("2021-13-01T00:00:00Z", None),
+            ("2021-01-00T00:00:00Z", None),
+            ("2021-01-01T24:00:00Z", None),
+            ("2021-01-01T00:60:00Z", None),
+            ("2021-01-01T00:00:61Z", None),
+            ("2021-01-01 00:00:00Z", None),
+            ("2021-01-01T00:00:00", None),
+            ("2021-01-01T00:00:00+00:00", None),
+            ("2021-1-01T00:00:000Z", None),
+            ("2021-+1-01T00:00:00Z", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_timestamp(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn timestamp_round_trip() {
+        for value in [
+            "1970-01-01T00:00:00Z",
+            "2004-02-29T12:34:56Z",
+            "2021-12-31T23:59:59Z",
+            "1600-03-01T00:00:00Z",
+        ] {
+            assert_eq!(format_timestamp(parse_timestamp(value).unwrap()), value);
+        }
+    }
+
+    #[test]
+    fn format_degrees() {
+        assert_eq!(format_nanodegrees(51_500_000_000), "51.5");
+        assert_eq!(format_nanodegrees(-100_000_000), "-0.1");
+        assert_eq!(format_nanodegrees(-1), "-0.000000001");
+        assert_eq!(format_nanodegrees(180_000_000_000), "180");
+    }
+}
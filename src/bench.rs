@@ -0,0 +1,315 @@
+//! The `bench` subcommand: repeated runs with timing statistics
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use osm_parse::OsmParseError;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+
+use crate::{
+    exit_unrecognised, exit_with_error, open, parse,
+    report::{OutputFormat, Report},
+    InputOptions,
+};
+
+/// Version of the benchmark JSON layout, as saved for baselines
+const SCHEMA_VERSION: u32 = 1;
+
+/// Exit code when a stage is slower than its baseline
+const EXIT_REGRESSION: i32 = 8;
+
+/// Measure parsing speed over repeated runs
+///
+///    Every run is timed per stage: decompression, tokenising and the
+///    total. The minimum, median, 95th percentile and standard deviation
+///    of each stage are reported.
+///
+///    A report saved with --save-baseline can be compared against with
+///    --baseline; a median slower than the baseline by more than the
+///    threshold is flagged as a regression, and exits with code 8.
+#[derive(StructOpt, Debug)]
+pub struct BenchOptions {
+    /// File to benchmark
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    #[structopt(flatten)]
+    input: InputOptions,
+
+    /// Number of measured runs
+    #[structopt(long, default_value = "5")]
+    runs: usize,
+
+    /// Number of unmeasured runs before the measured ones
+    #[structopt(long, default_value = "1")]
+    warmup: usize,
+
+    /// Read the file into memory first, so disk I/O is not measured
+    #[structopt(long)]
+    preload: bool,
+
+    /// Compare against a report saved with --save-baseline
+    #[structopt(long, parse(from_os_str))]
+    baseline: Option<PathBuf>,
+
+    /// Save the report as JSON, to compare later runs against
+    #[structopt(long, parse(from_os_str))]
+    save_baseline: Option<PathBuf>,
+
+    /// Slowdown of a median, in percent, flagged as a regression
+    #[structopt(long, default_value = "10")]
+    threshold: f64,
+
+    /// Report layout: text or json
+    #[structopt(long, default_value = "text")]
+    output: OutputFormat,
+}
+
+/// Stages of a run that are timed
+const STAGES: [&str; 3] = ["decompression", "tokenising", "total"];
+
+/// Summary of the timings of one stage, in seconds
+struct StageTimes {
+    min: f64,
+    median: f64,
+    p95: f64,
+    stddev: f64,
+    samples: Vec<f64>,
+}
+
+/// Run the benchmark and return the exit code
+pub fn run(options: &BenchOptions) -> i32 {
+    let preloaded: Option<Arc<[u8]>> = match options.preload {
+        true => match fs::read(&options.file) {
+            Ok(data) => Some(data.into()),
+            Err(e) => exit_with_error(&options.file, &e.into()),
+        },
+        false => None,
+    };
+
+    let mut reports = Vec::with_capacity(options.runs);
+    for run in 0..options.warmup + options.runs {
+        let report = match run_once(options, preloaded.as_ref()) {
+            Ok(Some(report)) => report,
+            Ok(None) => exit_unrecognised(),
+            Err(e) => exit_with_error(&options.file, &e),
+        };
+        if run >= options.warmup {
+            reports.push(report);
+        }
+    }
+
+    let stages: BTreeMap<_, _> = STAGES
+        .iter()
+        .map(|&stage| {
+            let samples = reports
+                .iter()
+                .map(|report| stage_duration(report, stage).as_secs_f64())
+                .collect();
+            (stage, StageTimes::new(samples))
+        })
+        .collect();
+
+    let regressions = match &options.baseline {
+        Some(baseline) => match read_baseline(baseline) {
+            Ok(baseline) => regressions(&stages, &baseline, options.threshold),
+            Err(e) => exit_with_error(baseline, &e.into()),
+        },
+        None => Vec::new(),
+    };
+
+    let json = to_json(options, reports.last(), &stages, &regressions);
+    if let Some(save_baseline) = &options.save_baseline {
+        if let Err(e) = fs::write(save_baseline, format!("{:#}\n", json)) {
+            exit_with_error(save_baseline, &e.into());
+        }
+    }
+
+    match options.output {
+        OutputFormat::Text => print_text(options, &stages, &regressions),
+        OutputFormat::Json => println!("{:#}", json),
+    }
+
+    match regressions.is_empty() {
+        true => 0,
+        false => EXIT_REGRESSION,
+    }
+}
+
+fn run_once(
+    options: &BenchOptions,
+    preloaded: Option<&Arc<[u8]>>,
+) -> Result<Option<Report>, OsmParseError> {
+    match preloaded {
+        Some(data) => {
+            let input: Box<dyn Read + Send> = Box::new(Cursor::new(Arc::clone(data)));
+            // The file is in memory already, so not mapped
+            let input_options = InputOptions {
+                #[cfg(feature = "mmap")]
+                no_mmap: true,
+                ..options.input
+            };
+            parse(input, Some(&options.file), &input_options, false)
+        }
+        None => {
+            let (input, path) = open(&options.file)?;
+            parse(input, path, &options.input, false)
+        }
+    }
+}
+
+fn stage_duration(report: &Report, stage: &str) -> Duration {
+    match stage {
+        "decompression" => report.decompression,
+        "tokenising" => report.tokenising(),
+        _ => report.elapsed,
+    }
+}
+
+impl StageTimes {
+    fn new(mut samples: Vec<f64>) -> Self {
+        samples.sort_by(f64::total_cmp);
+
+        let count = samples.len().max(1) as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / count;
+
+        Self {
+            min: samples.first().copied().unwrap_or_default(),
+            median: percentile(&samples, 50.0),
+            p95: percentile(&samples, 95.0),
+            stddev: variance.sqrt(),
+            samples,
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted `samples`
+fn percentile(samples: &[f64], percent: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let rank = (percent / 100.0 * samples.len() as f64).ceil() as usize;
+
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+/// Stage medians from a saved report
+fn read_baseline(path: &Path) -> io::Result<BTreeMap<String, f64>> {
+    let baseline: Value = serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(baseline["stages"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(stage, times)| Some((stage.clone(), times["median"].as_f64()?)))
+        .collect())
+}
+
+/// A stage whose median is slower than its baseline by more than `threshold`
+struct Regression {
+    stage: &'static str,
+    baseline: f64,
+    median: f64,
+}
+
+fn regressions(
+    stages: &BTreeMap<&'static str, StageTimes>,
+    baseline: &BTreeMap<String, f64>,
+    threshold: f64,
+) -> Vec<Regression> {
+    stages
+        .iter()
+        .filter_map(|(&stage, times)| {
+            let &baseline = baseline.get(stage)?;
+            (times.median > baseline * (1.0 + threshold / 100.0)).then_some(Regression {
+                stage,
+                baseline,
+                median: times.median,
+            })
+        })
+        .collect()
+}
+
+fn to_json(
+    options: &BenchOptions,
+    last: Option<&Report>,
+    stages: &BTreeMap<&str, StageTimes>,
+    regressions: &[Regression],
+) -> Value {
+    let stages: BTreeMap<_, _> = stages
+        .iter()
+        .map(|(stage, times)| {
+            (
+                stage,
+                json!({
+                    "min": times.min,
+                    "median": times.median,
+                    "p95": times.p95,
+                    "stddev": times.stddev,
+                    "samples": times.samples,
+                }),
+            )
+        })
+        .collect();
+    let regressions: Vec<_> = regressions
+        .iter()
+        .map(|regression| {
+            json!({
+                "stage": regression.stage,
+                "baseline_median": regression.baseline,
+                "median": regression.median,
+            })
+        })
+        .collect();
+
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "input": last.map(Report::input_json),
+        "runs": options.runs,
+        "warmup": options.warmup,
+        "preload": options.preload,
+        "jobs": options.input.jobs,
+        "stages": stages,
+        "regressions": regressions,
+    })
+}
+
+fn print_text(
+    options: &BenchOptions,
+    stages: &BTreeMap<&str, StageTimes>,
+    regressions: &[Regression],
+) {
+    println!(
+        "{}: {} runs after {} warmup{}",
+        options.file.display(),
+        options.runs,
+        options.warmup,
+        if options.preload { ", preloaded" } else { "" }
+    );
+    println!(
+        "\t{:<14}{:>10}{:>10}{:>10}{:>10}",
+        "stage", "min", "median", "p95", "stddev"
+    );
+    for stage in STAGES {
+        let times = &stages[stage];
+        println!(
+            "\t{:<14}{:>10.3}{:>10.3}{:>10.3}{:>10.3}",
+            stage, times.min, times.median, times.p95, times.stddev
+        );
+    }
+    for regression in regressions {
+        println!(
+            "\tREGRESSION {}: median {:.3} s, baseline {:.3} s",
+            regression.stage, regression.median, regression.baseline
+        );
+    }
+}
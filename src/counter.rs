@@ -0,0 +1,74 @@
+use std::{
+    io::{self, BufRead, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Counts the bytes passing through the wrapped reader
+///
+/// The count is shared, so it can be read while the reader is owned by
+/// a parser or another thread.
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, count: Arc<AtomicU64>) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count.fetch_add(amt as u64, Ordering::Relaxed);
+        self.inner.consume(amt);
+    }
+}
+
+/// Measures the time spent waiting on the wrapped reader
+///
+/// Wrapped around a decompressor, this separates the decompression time
+/// from the time spent on the data read. Timing every call has a cost, so
+/// read through a large buffer, such as a [`std::io::BufReader`].
+pub struct TimedReader<R> {
+    inner: R,
+    nanos: Arc<AtomicU64>,
+}
+
+impl<R> TimedReader<R> {
+    /// Wrap `inner`, adding the time spent in it to `nanos`, in nanoseconds
+    pub fn new(inner: R, nanos: Arc<AtomicU64>) -> Self {
+        Self { inner, nanos }
+    }
+
+    fn timed<T>(nanos: &AtomicU64, read: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = read();
+        nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+        result
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Self::timed(&self.nanos, || self.inner.read(buf))
+    }
+}
@@ -0,0 +1,191 @@
+use std::convert::TryFrom;
+
+/// The three kinds of top-level OSM elements, ordered as in sorted files
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+pub enum OsmTag {
+    Node,
+    Way,
+    Relation,
+}
+
+/// The blocks of an OsmChange document that wrap its elements
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// Key/value pairs of an element, in document order
+pub type Tags = Vec<(String, String)>;
+
+/// Editing metadata shared by nodes, ways and relations
+#[derive(Debug, Clone, PartialEq)]
+pub struct Meta {
+    pub version: Option<u32>,
+    /// Seconds since the Unix epoch
+    pub timestamp: Option<i64>,
+    pub changeset: Option<i64>,
+    pub user: Option<String>,
+    pub uid: Option<i64>,
+    pub visible: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Node {
+    pub id: i64,
+    /// Latitude in nanodegrees
+    pub lat: i64,
+    /// Longitude in nanodegrees
+    pub lon: i64,
+    pub meta: Meta,
+    pub tags: Tags,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Way {
+    pub id: i64,
+    pub meta: Meta,
+    pub tags: Tags,
+    /// Ids of the member nodes, in order
+    pub refs: Vec<i64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Relation {
+    pub id: i64,
+    pub meta: Meta,
+    pub tags: Tags,
+    pub members: Vec<Member>,
+}
+
+/// A `<member>` of a relation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub member_type: OsmTag,
+    pub member_ref: i64,
+    pub role: String,
+}
+
+/// A fully parsed top-level element, as yielded by [`crate::OsmReader::elements`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Node(Node),
+    Way(Way),
+    Relation(Relation),
+}
+
+/// An element along with the OsmChange action it appeared under, as
+/// yielded by [`crate::OsmReader::changes`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// `None` outside of a create, modify or delete block, as for all
+    /// elements of a plain OSM file
+    pub action: Option<Action>,
+    pub element: Element,
+}
+
+impl TryFrom<&[u8]> for OsmTag {
+    type Error = bool;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.eq_ignore_ascii_case(b"node") {
+            return Ok(Self::Node);
+        } else if value.eq_ignore_ascii_case(b"way") {
+            return Ok(Self::Way);
+        } else if value.eq_ignore_ascii_case(b"relation") {
+            return Ok(Self::Relation);
+        }
+
+        Err(false)
+    }
+}
+
+impl TryFrom<&[u8]> for Action {
+    type Error = bool;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.eq_ignore_ascii_case(b"create") {
+            return Ok(Self::Create);
+        } else if value.eq_ignore_ascii_case(b"modify") {
+            return Ok(Self::Modify);
+        } else if value.eq_ignore_ascii_case(b"delete") {
+            return Ok(Self::Delete);
+        }
+
+        Err(false)
+    }
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Self {
+            version: None,
+            timestamp: None,
+            changeset: None,
+            user: None,
+            uid: None,
+            visible: true,
+        }
+    }
+}
+
+impl Node {
+    pub fn latitude(&self) -> f64 {
+        self.lat as f64 / 1e9
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.lon as f64 / 1e9
+    }
+}
+
+impl Element {
+    pub fn tag(&self) -> OsmTag {
+        match self {
+            Self::Node(_) => OsmTag::Node,
+            Self::Way(_) => OsmTag::Way,
+            Self::Relation(_) => OsmTag::Relation,
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        match self {
+            Self::Node(node) => node.id,
+            Self::Way(way) => way.id,
+            Self::Relation(relation) => relation.id,
+        }
+    }
+
+    pub fn meta(&self) -> &Meta {
+        match self {
+            Self::Node(node) => &node.meta,
+            Self::Way(way) => &way.meta,
+            Self::Relation(relation) => &relation.meta,
+        }
+    }
+
+    pub fn tags(&self) -> &Tags {
+        match self {
+            Self::Node(node) => &node.tags,
+            Self::Way(way) => &way.tags,
+            Self::Relation(relation) => &relation.tags,
+        }
+    }
+
+    pub fn tags_mut(&mut self) -> &mut Tags {
+        match self {
+            Self::Node(node) => &mut node.tags,
+            Self::Way(way) => &mut way.tags,
+            Self::Relation(relation) => &mut relation.tags,
+        }
+    }
+
+    /// Value of the tag with key `key`, if present
+    pub fn tag_value(&self, key: &str) -> Option<&str> {
+        self.tags()
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
@@ -0,0 +1,228 @@
+use std::{error::Error, fmt, io};
+
+use crate::OsmTag;
+
+/// Location in the decompressed input at which an error was found
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset; for PBF input, the offset of the blob in error
+    pub offset: u64,
+    /// Line number, only known for XML input
+    pub line: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum OsmParseError {
+    Io {
+        source: io::Error,
+        position: Position,
+    },
+    Decompression {
+        source: io::Error,
+        position: Position,
+    },
+    Xml {
+        source: quick_xml::Error,
+        position: Position,
+    },
+    Pbf {
+        message: String,
+        position: Position,
+    },
+    MissingAttribute {
+        element: OsmTag,
+        name: &'static str,
+        position: Position,
+    },
+    BadAttribute {
+        element: OsmTag,
+        name: String,
+        value: String,
+        position: Position,
+    },
+}
+
+/// A flaw in the nesting of elements; parsing carries on past it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StructureIssue {
+    /// Element `open` left open by the end tag `found` of an element
+    /// around it
+    Mismatch {
+        open: String,
+        found: String,
+        position: Position,
+    },
+    /// End tag `found` without a matching open element
+    UnexpectedEnd { found: String, position: Position },
+    /// An OSM element inside another one, such as a node in a way; the
+    /// inner element is skipped
+    Nested {
+        element: OsmTag,
+        parent: OsmTag,
+        position: Position,
+    },
+    /// Element `open` not closed at the end of the input
+    Unclosed { open: String, position: Position },
+}
+
+/// A tag expression that cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    pub message: String,
+    /// Character offset in the expression
+    pub offset: usize,
+}
+
+/// Marks an I/O error as coming from a decompressor
+#[derive(Debug)]
+pub(crate) struct DecompressionError(pub io::Error);
+
+impl OsmParseError {
+    pub(crate) fn pbf(message: impl Into<String>) -> Self {
+        Self::Pbf {
+            message: message.into(),
+            position: Position::default(),
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        match self {
+            Self::Io { position, .. }
+            | Self::Decompression { position, .. }
+            | Self::Xml { position, .. }
+            | Self::Pbf { position, .. }
+            | Self::MissingAttribute { position, .. }
+            | Self::BadAttribute { position, .. } => *position,
+        }
+    }
+
+    /// Set the position at which this error was found
+    pub(crate) fn at(mut self, at: Position) -> Self {
+        match &mut self {
+            Self::Io { position, .. }
+            | Self::Decompression { position, .. }
+            | Self::Xml { position, .. }
+            | Self::Pbf { position, .. }
+            | Self::MissingAttribute { position, .. }
+            | Self::BadAttribute { position, .. } => *position = at,
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}, byte offset {}", line, self.offset),
+            None => write!(f, "byte offset {}", self.offset),
+        }
+    }
+}
+
+impl fmt::Display for OsmParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.position())?;
+
+        match self {
+            Self::Io { source, .. } => write!(f, "I/O error: {}", source),
+            Self::Decompression { source, .. } => write!(f, "decompression error: {}", source),
+            Self::Xml { source, .. } => write!(f, "XML error: {}", source),
+            Self::Pbf { message, .. } => write!(f, "PBF error: {}", message),
+            Self::MissingAttribute { element, name, .. } => {
+                write!(f, "{:?} without required attribute '{}'", element, name)
+            }
+            Self::BadAttribute {
+                element,
+                name,
+                value,
+                ..
+            } => write!(f, "{:?} has invalid {}=\"{}\"", element, name, value),
+        }
+    }
+}
+
+impl StructureIssue {
+    pub fn position(&self) -> Position {
+        match self {
+            Self::Mismatch { position, .. }
+            | Self::UnexpectedEnd { position, .. }
+            | Self::Nested { position, .. }
+            | Self::Unclosed { position, .. } => *position,
+        }
+    }
+}
+
+impl fmt::Display for StructureIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.position())?;
+
+        match self {
+            Self::Mismatch { open, found, .. } => write!(f, "<{}> closed by </{}>", open, found),
+            Self::UnexpectedEnd { found, .. } => write!(f, "</{}> without open element", found),
+            Self::Nested {
+                element, parent, ..
+            } => write!(f, "{:?} inside {:?}", element, parent),
+            Self::Unclosed { open, .. } => write!(f, "<{}> not closed at end of input", open),
+        }
+    }
+}
+
+impl Error for OsmParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } | Self::Decompression { source, .. } => Some(source),
+            Self::Xml { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at character {}", self.message, self.offset)
+    }
+}
+
+impl Error for ExpressionError {}
+
+impl fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for DecompressionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl From<io::Error> for OsmParseError {
+    fn from(e: io::Error) -> Self {
+        let position = Position::default();
+
+        match e.downcast::<DecompressionError>() {
+            Ok(DecompressionError(source)) => Self::Decompression { source, position },
+            Err(source) => Self::Io { source, position },
+        }
+    }
+}
+
+impl From<quick_xml::Error> for OsmParseError {
+    fn from(e: quick_xml::Error) -> Self {
+        match e {
+            quick_xml::Error::Io(e) => e.into(),
+            source => Self::Xml {
+                source,
+                position: Position::default(),
+            },
+        }
+    }
+}
+
+impl From<quick_xml::events::attributes::AttrError> for OsmParseError {
+    fn from(e: quick_xml::events::attributes::AttrError) -> Self {
+        quick_xml::Error::from(e).into()
+    }
+}
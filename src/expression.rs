@@ -0,0 +1,435 @@
+//! Tag expressions that select elements
+//!
+//! ```text
+//! amenity=restaurant or shop=*
+//! w/highway and not highway~"^(footway|path)$"
+//! nw/name!=""
+//! ```
+//!
+//! A test is a key, optionally followed by an operator and a value:
+//! `key` and `key=*` test that the key is present, `key=value` and
+//! `key!=value` compare its value, and `key~regex` and `key!~regex`
+//! search its value for a regular expression. Negated tests only match
+//! elements that have the key. A prefix of `n`, `w` and `r` letters and a
+//! slash limits a test to nodes, ways and relations; `w/*` matches every
+//! way. Tests are combined with `and`, `or`, `not` (or `!`) and
+//! parentheses. Values with spaces, parentheses or operator characters
+//! are written in single or double quotes.
+
+use std::{iter::Peekable, str::FromStr};
+
+use regex::Regex;
+
+use crate::{Element, ExpressionError, OsmTag};
+
+/// A parsed tag expression
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Or(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Test(TagTest),
+}
+
+/// A test of a single key
+#[derive(Debug, Clone)]
+pub struct TagTest {
+    /// Tags the test applies to, or `None` for all
+    types: Option<Vec<OsmTag>>,
+    /// `None` for `*`, which matches any element
+    key: Option<String>,
+    value: ValueTest,
+    negated: bool,
+}
+
+#[derive(Debug, Clone)]
+enum ValueTest {
+    Any,
+    Equals(String),
+    Regex(Regex),
+}
+
+enum Token {
+    Open,
+    Close,
+    /// Characters of a word, each marked whether it was quoted
+    Word(Vec<(char, bool)>),
+}
+
+/// Operators, longest first so `!=` is not taken for `!`
+const OPERATORS: [&str; 4] = ["!=", "!~", "=", "~"];
+
+impl Expression {
+    pub fn matches(&self, element: &Element) -> bool {
+        match self {
+            Self::Or(left, right) => left.matches(element) || right.matches(element),
+            Self::And(left, right) => left.matches(element) && right.matches(element),
+            Self::Not(expression) => !expression.matches(element),
+            Self::Test(test) => test.matches(element),
+        }
+    }
+}
+
+impl TagTest {
+    fn matches(&self, element: &Element) -> bool {
+        if let Some(types) = &self.types {
+            if !types.contains(&element.tag()) {
+                return false;
+            }
+        }
+        let key = match &self.key {
+            Some(key) => key,
+            None => return true,
+        };
+
+        match (element.tag_value(key), &self.value) {
+            (None, _) => false,
+            (Some(_), ValueTest::Any) => true,
+            (Some(value), ValueTest::Equals(expected)) => (value == expected) != self.negated,
+            (Some(value), ValueTest::Regex(regex)) => regex.is_match(value) != self.negated,
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenise(s)?.into_iter().peekable();
+        let expression = parse_or(&mut tokens, s)?;
+
+        match tokens.next() {
+            None => Ok(expression),
+            Some((_, offset)) => Err(error("expected 'and', 'or' or the end", offset)),
+        }
+    }
+}
+
+type Tokens = Peekable<std::vec::IntoIter<(Token, usize)>>;
+
+fn tokenise(s: &str) -> Result<Vec<(Token, usize)>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut word: Vec<(char, bool)> = Vec::new();
+    let mut word_start = 0;
+    let mut quote: Option<(char, usize)> = None;
+
+    for (offset, c) in s.chars().enumerate() {
+        match (quote, c) {
+            (Some((open, _)), c) if c == open => quote = None,
+            (Some(_), c) => word.push((c, true)),
+            (None, '"' | '\'') => {
+                if word.is_empty() {
+                    word_start = offset;
+                }
+                quote = Some((c, offset));
+            }
+            (None, c) if c.is_whitespace() || c == '(' || c == ')' => {
+                if !word.is_empty() {
+                    tokens.push((Token::Word(std::mem::take(&mut word)), word_start));
+                }
+                match c {
+                    '(' => tokens.push((Token::Open, offset)),
+                    ')' => tokens.push((Token::Close, offset)),
+                    _ => (),
+                }
+            }
+            (None, c) => {
+                if word.is_empty() {
+                    word_start = offset;
+                }
+                word.push((c, false));
+            }
+        }
+    }
+
+    if let Some((_, offset)) = quote {
+        return Err(error("unterminated quote", offset));
+    }
+    if !word.is_empty() {
+        tokens.push((Token::Word(word), word_start));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_or(tokens: &mut Tokens, s: &str) -> Result<Expression, ExpressionError> {
+    let mut expression = parse_and(tokens, s)?;
+    while next_keyword(tokens, "or") {
+        expression = Expression::Or(Box::new(expression), Box::new(parse_and(tokens, s)?));
+    }
+
+    Ok(expression)
+}
+
+fn parse_and(tokens: &mut Tokens, s: &str) -> Result<Expression, ExpressionError> {
+    let mut expression = parse_unary(tokens, s)?;
+    while next_keyword(tokens, "and") {
+        expression = Expression::And(Box::new(expression), Box::new(parse_unary(tokens, s)?));
+    }
+
+    Ok(expression)
+}
+
+fn parse_unary(tokens: &mut Tokens, s: &str) -> Result<Expression, ExpressionError> {
+    if next_keyword(tokens, "not") || next_keyword(tokens, "!") {
+        return Ok(Expression::Not(Box::new(parse_unary(tokens, s)?)));
+    }
+
+    match tokens.next() {
+        Some((Token::Open, _)) => {
+            let expression = parse_or(tokens, s)?;
+            match tokens.next() {
+                Some((Token::Close, _)) => Ok(expression),
+                Some((_, offset)) => Err(error("expected ')'", offset)),
+                None => Err(error("expected ')'", s.chars().count())),
+            }
+        }
+        Some((Token::Close, offset)) => Err(error("unexpected ')'", offset)),
+        // `!key` is short for `not key`
+        Some((Token::Word(word), offset)) => match word.split_first() {
+            Some((('!', false), rest)) if !rest.is_empty() => Ok(Expression::Not(Box::new(
+                Expression::Test(parse_test(rest, offset + 1)?),
+            ))),
+            _ => Ok(Expression::Test(parse_test(&word, offset)?)),
+        },
+        None => Err(error("expected a tag test", s.chars().count())),
+    }
+}
+
+/// Consume the next token when it is the unquoted `keyword`
+fn next_keyword(tokens: &mut Tokens, keyword: &str) -> bool {
+    tokens
+        .next_if(|(token, _)| match token {
+            Token::Word(word) => {
+                word.iter().all(|&(_, quoted)| !quoted)
+                    && word
+                        .iter()
+                        .map(|&(c, _)| c)
+                        .collect::<String>()
+                        .eq_ignore_ascii_case(keyword)
+            }
+            _ => false,
+        })
+        .is_some()
+}
+
+fn parse_test(word: &[(char, bool)], offset: usize) -> Result<TagTest, ExpressionError> {
+    let text = |range: &[(char, bool)]| -> String { range.iter().map(|&(c, _)| c).collect() };
+
+    // A type prefix such as `w/` or `nw/`
+    let (types, word, offset) = match word.iter().position(|&c| c == ('/', false)) {
+        Some(slash) if slash > 0 && word[..slash].iter().all(|&(c, q)| !q && "nwr".contains(c)) => {
+            let types = word[..slash]
+                .iter()
+                .map(|&(c, _)| match c {
+                    'n' => OsmTag::Node,
+                    'w' => OsmTag::Way,
+                    _ => OsmTag::Relation,
+                })
+                .collect();
+            (Some(types), &word[slash + 1..], offset + slash + 1)
+        }
+        _ => (None, word, offset),
+    };
+
+    let operator = (0..word.len()).find_map(|start| {
+        OPERATORS
+            .iter()
+            .find(|operator| {
+                let end = start + operator.chars().count();
+                end <= word.len()
+                    && word[start..end]
+                        .iter()
+                        .zip(operator.chars())
+                        .all(|(&(c, quoted), expected)| !quoted && c == expected)
+            })
+            .map(|&operator| (start, operator))
+    });
+    let key = &word[..operator.map_or(word.len(), |(start, _)| start)];
+    if key.is_empty() {
+        return Err(error("expected a key", offset));
+    }
+    let key = match (key, operator) {
+        ([('*', false)], None) => None,
+        (key, _) => Some(text(key)),
+    };
+
+    let (value, negated) = match operator {
+        None => (ValueTest::Any, false),
+        Some((start, operator)) => {
+            let value_start = start + operator.chars().count();
+            let value_offset = offset + value_start;
+            match (operator, &word[value_start..]) {
+                ("=", [('*', false)]) => (ValueTest::Any, false),
+                ("!=", [('*', false)]) => {
+                    return Err(error(
+                        "use 'not key' for elements without a key",
+                        value_offset,
+                    ))
+                }
+                ("=" | "!=", value) => (ValueTest::Equals(text(value)), operator == "!="),
+                (_, value) => match Regex::new(&text(value)) {
+                    Ok(regex) => (ValueTest::Regex(regex), operator == "!~"),
+                    Err(e) => return Err(error(&format!("invalid regex: {}", e), value_offset)),
+                },
+            }
+        }
+    };
+
+    Ok(TagTest {
+        types,
+        key,
+        value,
+        negated,
+    })
+}
+
+fn error(message: &str, offset: usize) -> ExpressionError {
+    ExpressionError {
+        message: message.to_string(),
+        offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Node, Relation, Way};
+
+    fn node(tags: &[(&str, &str)]) -> Element {
+        Element::Node(Node {
+            tags: tags
+                .iter()
+                .map(|&(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ..Node::default()
+        })
+    }
+
+    fn way(tags: &[(&str, &str)]) -> Element {
+        match node(tags) {
+            Element::Node(node) => Element::Way(Way {
+                tags: node.tags,
+                ..Way::default()
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn node_with(key: &str) -> Element {
+        node(&[(key, "")])
+    }
+
+    fn matches(expression: &str, element: &Element) -> bool {
+        expression
+            .parse::<Expression>()
+            .unwrap_or_else(|e| panic!("{}: {}", expression, e))
+            .matches(element)
+    }
+
+    fn error_at(expression: &str) -> (String, usize) {
+        match expression.parse::<Expression>() {
+            Ok(parsed) => panic!("{} parsed as {:?}", expression, parsed),
+            Err(e) => (e.message, e.offset),
+        }
+    }
+
+    #[test]
+    fn quoting() {
+        let restaurant = node(&[("name", "Chez (Paul)"), ("a=b", "c d")]);
+        assert!(matches("name=\"Chez (Paul)\"", &restaurant));
+        assert!(matches("name='Chez (Paul)'", &restaurant));
+        assert!(matches("'a=b'='c d'", &restaurant));
+        assert!(!matches("name=Chez", &node(&[("name", "Chez (Paul)")])));
+        // Quoted keywords and operators are plain text
+        assert!(matches("'not'", &node(&[("not", "x")])));
+        assert!(matches("key='!=*'", &node(&[("key", "!=*")])));
+        assert!(matches("key=\"it's\"", &node(&[("key", "it's")])));
+        assert!(matches("key=''", &node(&[("key", "")])));
+    }
+
+    #[test]
+    fn not_equal_and_missing_key() {
+        let restaurant = node(&[("amenity", "restaurant")]);
+        let cafe = node(&[("amenity", "cafe")]);
+        let shop = node(&[("shop", "bakery")]);
+
+        // `!=` needs the key, `!key` needs it to be missing
+        assert!(!matches("amenity!=restaurant", &restaurant));
+        assert!(matches("amenity!=restaurant", &cafe));
+        assert!(!matches("amenity!=restaurant", &shop));
+        assert!(!matches("!amenity", &restaurant));
+        assert!(matches("!amenity", &shop));
+        assert!(matches("not amenity", &shop));
+        assert!(matches("! amenity", &shop));
+        assert!(matches("not amenity=restaurant", &shop));
+
+        assert!(matches("amenity!~^rest", &cafe));
+        assert!(!matches("amenity!~^rest", &shop));
+    }
+
+    #[test]
+    fn type_prefix() {
+        let node = node(&[("name", "x")]);
+        let way = way(&[("name", "x")]);
+        let relation = Element::Relation(Relation::default());
+
+        assert!(matches("nw/name", &node));
+        assert!(matches("nw/name", &way));
+        assert!(!matches("w/name", &node));
+        assert!(matches("r/*", &relation));
+        assert!(!matches("nw/*", &relation));
+        assert!(!matches("n/name!=x", &node));
+        // Not a prefix unless all letters are types
+        assert!(matches("a/b", &node_with("a/b")));
+        assert!(matches("'n/name'", &node_with("n/name")));
+    }
+
+    #[test]
+    fn precedence() {
+        let a = node(&[("a", "")]);
+        let b = node(&[("b", "")]);
+        let c = node(&[("c", "")]);
+        let bc = node(&[("b", ""), ("c", "")]);
+
+        // `a or b and c` is `a or (b and c)`
+        assert!(matches("a or b and c", &a));
+        assert!(!matches("a or b and c", &b));
+        assert!(matches("a or b and c", &bc));
+        assert!(!matches("(a or b) and c", &a));
+        assert!(matches("(a or b) and c", &bc));
+        assert!(matches("b and c or a", &a));
+        // `not` binds tighter than `and`
+        assert!(matches("not a and c", &c));
+        assert!(!matches("not (a or c) and c", &c));
+        assert!(matches("A OR b", &b));
+    }
+
+    #[test]
+    fn error_offsets() {
+        let cases = [
+            ("a and", "expected a tag test", 5),
+            ("(a", "expected ')'", 2),
+            ("(a b", "expected ')'", 3),
+            ("a b", "expected 'and', 'or' or the end", 2),
+            (")", "unexpected ')'", 0),
+            ("a or \"b", "unterminated quote", 5),
+            ("=x", "expected a key", 0),
+            ("w/=x", "expected a key", 2),
+            ("a!=*", "use 'not key' for elements without a key", 3),
+            ("", "expected a tag test", 0),
+        ];
+        for (expression, message, offset) in cases {
+            assert_eq!(
+                error_at(expression),
+                (message.to_string(), offset),
+                "{}",
+                expression
+            );
+        }
+
+        let (message, offset) = error_at("nw/key~'['");
+        assert!(message.starts_with("invalid regex"), "{}", message);
+        assert_eq!(offset, 7);
+    }
+}
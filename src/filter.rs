@@ -0,0 +1,172 @@
+//! The `filter` subcommand: the elements matching a tag expression
+
+use std::{
+    collections::HashSet,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use osm_parse::{Element, Expression, FileFormats, OsmParseError, OsmReader, OsmTag, OsmWriter};
+use structopt::StructOpt;
+
+use crate::{exit_unrecognised, exit_with_error, output::Output};
+
+/// Write the elements matching a tag expression
+///
+///    For example 'amenity=restaurant or shop=*'. A test is a key,
+///    optionally with =value, !=value, ~regex or !~regex; key and key=*
+///    test that the key is present. A prefix such as w/ or nw/ limits a
+///    test to nodes, ways and relations, so w/* matches every way. Tests
+///    are combined with and, or, not and parentheses; values with spaces
+///    or parentheses are quoted.
+///
+///    With --add-referenced, the nodes of matching ways and the members
+///    of matching relations are written as well, including the nodes of
+///    member ways. The input is then read up to three times.
+///
+///    An existing output file is only replaced once the result is
+///    complete.
+#[derive(StructOpt, Debug)]
+pub struct FilterOptions {
+    /// Tag expression selecting the elements to write
+    expression: Expression,
+
+    /// OSM file to filter
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// Write the result to this file instead of standard output
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Also write the elements that matching ways and relations refer to
+    #[structopt(short = "r", long)]
+    add_referenced: bool,
+}
+
+/// Ids of the elements referred to by matching ones
+#[derive(Default)]
+struct Referenced {
+    nodes: HashSet<i64>,
+    ways: HashSet<i64>,
+    relations: HashSet<i64>,
+}
+
+/// Filter the file and return the exit code
+pub fn run(options: &FilterOptions) -> i32 {
+    let mut referenced = Referenced::default();
+    if options.add_referenced {
+        let result = collect_referenced(options, &mut referenced);
+        if let Err(e) = result {
+            exit_with_error(&options.file, &e);
+        }
+    }
+
+    let reader = match open_reader(&options.file) {
+        Ok(reader) => reader,
+        Err(e) => exit_with_error(&options.file, &e),
+    };
+    let (output, writer) = Output::create(options.output.as_deref());
+    let (mut matched, mut written) = (0, 0);
+    let write = || -> Result<(), OsmParseError> {
+        let mut writer = OsmWriter::new(writer)?;
+        for_each_element(reader, |element| {
+            let matches = options.expression.matches(&element);
+            if matches || referenced.contains(&element) {
+                matched += u64::from(matches);
+                written += 1;
+                writer.write(&element)?;
+            }
+            Ok(())
+        })?;
+        writer.finish()?;
+
+        Ok(())
+    };
+
+    match output.finish(write()) {
+        Ok(()) => {
+            eprintln!("{} elements written, {} matching", written, matched);
+            0
+        }
+        Err(e) => exit_with_error(&options.file, &e),
+    }
+}
+
+/// Read the file for the elements that matching ways and relations refer
+/// to, and again for the nodes of the ways among them
+fn collect_referenced(
+    options: &FilterOptions,
+    referenced: &mut Referenced,
+) -> Result<(), OsmParseError> {
+    for_each_element(open_reader(&options.file)?, |element| {
+        if options.expression.matches(&element) {
+            referenced.add_members(&element);
+        }
+        Ok(())
+    })?;
+
+    if referenced.ways.is_empty() {
+        return Ok(());
+    }
+    for_each_element(open_reader(&options.file)?, |element| {
+        match &element {
+            Element::Way(way) if referenced.ways.contains(&way.id) => {
+                referenced.nodes.extend(&way.refs)
+            }
+            _ => (),
+        }
+        Ok(())
+    })
+}
+
+fn open_reader(path: &Path) -> Result<OsmReader<Box<dyn BufRead>>, OsmParseError> {
+    match FileFormats::open(path)? {
+        Some(reader) => Ok(reader),
+        None => exit_unrecognised(),
+    }
+}
+
+fn for_each_element(
+    mut reader: OsmReader<Box<dyn BufRead>>,
+    mut visit: impl FnMut(Element) -> Result<(), OsmParseError>,
+) -> Result<(), OsmParseError> {
+    for element in reader.elements() {
+        visit(element?)?;
+    }
+
+    Ok(())
+}
+
+impl Referenced {
+    /// Add the nodes of a way, or the members of a relation
+    fn add_members(&mut self, element: &Element) {
+        match element {
+            Element::Node(_) => (),
+            Element::Way(way) => self.nodes.extend(&way.refs),
+            Element::Relation(relation) => {
+                for member in &relation.members {
+                    self.ids_mut(member.member_type).insert(member.member_ref);
+                }
+            }
+        }
+    }
+
+    fn contains(&self, element: &Element) -> bool {
+        let ids = match element.tag() {
+            OsmTag::Node => &self.nodes,
+            OsmTag::Way => &self.ways,
+            OsmTag::Relation => &self.relations,
+        };
+
+        ids.contains(&element.id())
+    }
+
+    fn ids_mut(&mut self, tag: OsmTag) -> &mut HashSet<i64> {
+        match tag {
+            OsmTag::Node => &mut self.nodes,
+            OsmTag::Way => &mut self.ways,
+            OsmTag::Relation => &mut self.relations,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Versions of the objects in full-history files
+//!
+//! History files hold every version of every object, sorted by OSM tag,
+//! id and version, so all versions of an object follow each other.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{Element, OsmTag};
+
+/// Counts of the objects of one OSM tag in a history file
+#[derive(Default, Debug, Clone)]
+pub struct HistoryInfo {
+    /// Distinct ids
+    pub objects: u64,
+    pub versions: u64,
+    /// Objects whose last version is deleted
+    pub deleted: u64,
+    /// Number of objects per number of versions they have
+    pub distribution: BTreeMap<u64, u64>,
+}
+
+/// Object counts of a history file, per OSM tag
+#[derive(Default, Debug, Clone)]
+pub struct History {
+    pub info: HashMap<OsmTag, HistoryInfo>,
+    /// Object of the last version counted, with its number of versions
+    /// and whether its last version is deleted
+    current: Option<(OsmTag, i64, u64, bool)>,
+}
+
+impl History {
+    /// Count the next version in the file
+    ///
+    /// The counts are kept up to date after every version, so there is
+    /// nothing to finish at the end of the file.
+    pub fn count(&mut self, element: &Element) {
+        let (tag, id, deleted) = (element.tag(), element.id(), !element.meta().visible);
+        let info = self.info.entry(tag).or_default();
+        info.versions += 1;
+
+        match &mut self.current {
+            Some((current_tag, current_id, versions, was_deleted))
+                if *current_tag == tag && *current_id == id =>
+            {
+                decrement(&mut info.distribution, *versions);
+                *versions += 1;
+                *info.distribution.entry(*versions).or_default() += 1;
+
+                match (*was_deleted, deleted) {
+                    (false, true) => info.deleted += 1,
+                    (true, false) => info.deleted -= 1,
+                    _ => (),
+                }
+                *was_deleted = deleted;
+            }
+            _ => {
+                info.objects += 1;
+                *info.distribution.entry(1).or_default() += 1;
+                if deleted {
+                    info.deleted += 1;
+                }
+                self.current = Some((tag, id, 1, deleted));
+            }
+        }
+    }
+
+    /// Add the counts of `other` to these
+    pub fn merge(&mut self, other: History) {
+        for (tag, other_info) in other.info {
+            let info = self.info.entry(tag).or_default();
+            info.objects += other_info.objects;
+            info.versions += other_info.versions;
+            info.deleted += other_info.deleted;
+            for (versions, objects) in other_info.distribution {
+                *info.distribution.entry(versions).or_default() += objects;
+            }
+        }
+    }
+}
+
+fn decrement(distribution: &mut BTreeMap<u64, u64>, versions: u64) {
+    if let Some(objects) = distribution.get_mut(&versions) {
+        *objects -= 1;
+        if *objects == 0 {
+            distribution.remove(&versions);
+        }
+    }
+}
+
+/// Reduces the versions in a history file to the objects as they were at
+/// a point in time
+///
+/// Versions are pushed in file order; each object is yielded once its
+/// last version is seen, unless it did not exist yet or was deleted.
+/// Versions without a timestamp are taken to be old enough.
+pub struct Snapshot {
+    /// Seconds since the Unix epoch
+    at: i64,
+    /// Object of the last version pushed
+    current: Option<(OsmTag, i64)>,
+    /// Latest version of that object as of `at`
+    pending: Option<Element>,
+}
+
+impl Snapshot {
+    /// Snapshot as of `at`, in seconds since the Unix epoch
+    pub fn new(at: i64) -> Self {
+        Self {
+            at,
+            current: None,
+            pending: None,
+        }
+    }
+
+    /// Add the next version, and return the previous object when `element`
+    /// is a version of another one
+    pub fn push(&mut self, element: Element) -> Option<Element> {
+        let key = (element.tag(), element.id());
+        let done = match self.current == Some(key) {
+            true => None,
+            false => self.take(),
+        };
+        self.current = Some(key);
+
+        if element
+            .meta()
+            .timestamp
+            .is_none_or(|timestamp| timestamp <= self.at)
+        {
+            self.pending = Some(element);
+        }
+
+        done
+    }
+
+    /// The last object, once all versions are pushed
+    pub fn finish(mut self) -> Option<Element> {
+        self.take()
+    }
+
+    fn take(&mut self) -> Option<Element> {
+        self.pending.take().filter(|element| element.meta().visible)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OsmReader;
+
+    fn elements(document: &str) -> Vec<Element> {
+        OsmReader::new(document.as_bytes())
+            .elements()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    /// Versions, deleted objects and distribution of the nodes counted
+    fn node_counts(history: &History) -> (u64, u64, Vec<(u64, u64)>) {
+        let info = &history.info[&OsmTag::Node];
+        let distribution = info.distribution.iter().map(|(&v, &o)| (v, o)).collect();
+
+        (info.versions, info.deleted, distribution)
+    }
+
+    #[test]
+    fn count_deletions_and_distribution() {
+        let document = r#"<osm>
+            <node id="1" version="1" visible="true" lat="1" lon="1"/>
+            <node id="1" version="2" visible="false"/>
+            <node id="1" version="3" visible="true" lat="1" lon="1"/>
+            <node id="2" version="1" visible="true" lat="1" lon="1"/>
+            <node id="2" version="2" visible="false"/>
+            <way id="1" version="1" visible="false"/>
+            </osm>"#;
+        let mut versions = elements(document).into_iter();
+        let mut history = History::default();
+
+        history.count(&versions.next().unwrap());
+        assert_eq!(node_counts(&history), (1, 0, vec![(1, 1)]));
+        history.count(&versions.next().unwrap());
+        assert_eq!(node_counts(&history), (2, 1, vec![(2, 1)]));
+        // Restored again
+        history.count(&versions.next().unwrap());
+        assert_eq!(node_counts(&history), (3, 0, vec![(3, 1)]));
+
+        for version in versions {
+            history.count(&version);
+        }
+        assert_eq!(node_counts(&history), (5, 1, vec![(2, 1), (3, 1)]));
+        assert_eq!(history.info[&OsmTag::Node].objects, 2);
+
+        // Deleted in its first version
+        let ways = &history.info[&OsmTag::Way];
+        assert_eq!((ways.objects, ways.versions, ways.deleted), (1, 1, 1));
+    }
+
+    #[test]
+    fn snapshot() {
+        let document = r#"<osm>
+            <node id="1" version="1" timestamp="2021-01-01T00:00:00Z" lat="1" lon="1"/>
+            <node id="2" version="1" timestamp="2019-01-01T00:00:00Z" lat="1" lon="1"/>
+            <node id="2" version="2" timestamp="2020-01-01T00:00:00Z" visible="false"/>
+            <node id="3" version="1" timestamp="2019-01-01T00:00:00Z" lat="1" lon="1"/>
+            <node id="3" version="2" timestamp="2020-03-01T00:00:00Z" lat="1" lon="1"/>
+            <node id="3" version="3" timestamp="2021-01-01T00:00:00Z" lat="1" lon="1"/>
+            <node id="4" version="1" timestamp="2019-01-01T00:00:00Z" lat="1" lon="1"/>
+            <node id="4" version="2" timestamp="2021-01-01T00:00:00Z" visible="false"/>
+            <way id="4" version="1"/>
+            </osm>"#;
+        // 2020-06-01
+        let mut snapshot = Snapshot::new(1_590_969_600);
+        let mut objects = Vec::new();
+        for element in elements(document) {
+            objects.extend(snapshot.push(element));
+        }
+        objects.extend(snapshot.finish());
+
+        let objects: Vec<_> = objects
+            .iter()
+            .map(|element| (element.tag(), element.id(), element.meta().version))
+            .collect();
+        assert_eq!(
+            objects,
+            [
+                // Node 1 did not exist yet, and node 2 was deleted by then
+                (OsmTag::Node, 3, Some(2)),
+                // Deleted only later
+                (OsmTag::Node, 4, Some(1)),
+                // Without a timestamp
+                (OsmTag::Way, 4, Some(1)),
+            ]
+        );
+    }
+}
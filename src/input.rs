@@ -0,0 +1,258 @@
+use std::{
+    convert::TryFrom,
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufRead, BufReader, Chain, Cursor, Read},
+    path::Path,
+    str::FromStr,
+    thread,
+};
+
+use crate::{error::DecompressionError, OsmReader, ParallelBzDecoder};
+
+/// Number of leading bytes needed to recognise any of the formats
+const MAGIC_LENGTH: usize = 16;
+
+/// Input whose leading bytes were read ahead by [`FileFormats::detect`]
+pub type DetectedInput<R> = BufReader<Chain<Cursor<Vec<u8>>, R>>;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormats {
+    XML,
+    BZIP2,
+    #[cfg(feature = "gzip")]
+    GZIP,
+    #[cfg(feature = "zstd")]
+    ZSTD,
+    #[cfg(feature = "xz")]
+    XZ,
+    PBF,
+}
+
+impl TryFrom<&OsStr> for FileFormats {
+    type Error = bool;
+
+    fn try_from(os_str: &OsStr) -> Result<Self, Self::Error> {
+        if let Some(s) = os_str.to_str() {
+            if s.ends_with("osm.pbf") || s.ends_with("osh.pbf") {
+                return Ok(Self::PBF);
+            } else if is_document(s, ".bz2") {
+                return Ok(Self::BZIP2);
+            }
+            #[cfg(feature = "gzip")]
+            if is_document(s, ".gz") {
+                return Ok(Self::GZIP);
+            }
+            #[cfg(feature = "zstd")]
+            if is_document(s, ".zst") {
+                return Ok(Self::ZSTD);
+            }
+            #[cfg(feature = "xz")]
+            if is_document(s, ".xz") {
+                return Ok(Self::XZ);
+            }
+            if is_document(s, "") {
+                return Ok(Self::XML);
+            }
+        }
+
+        Err(false)
+    }
+}
+
+/// Whether `s` is the name of an OSM, OsmChange or full-history document,
+/// ending in `suffix`
+fn is_document(s: &str, suffix: &str) -> bool {
+    s.strip_suffix(suffix).is_some_and(|s| {
+        ["osm", "osc", "osh"]
+            .iter()
+            .any(|document| s.ends_with(document))
+    })
+}
+
+impl TryFrom<&[u8]> for FileFormats {
+    type Error = bool;
+
+    /// Recognise a format from the first bytes of its content
+    fn try_from(magic: &[u8]) -> Result<Self, Self::Error> {
+        if magic.len() >= 4 && magic.starts_with(b"BZh") && (b'1'..=b'9').contains(&magic[3]) {
+            return Ok(Self::BZIP2);
+        }
+        #[cfg(feature = "gzip")]
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            return Ok(Self::GZIP);
+        }
+        #[cfg(feature = "zstd")]
+        if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Ok(Self::ZSTD);
+        }
+        #[cfg(feature = "xz")]
+        if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            return Ok(Self::XZ);
+        }
+        // The length of the first BlobHeader, followed by its `type` field
+        if magic.len() >= 15 && magic[..2] == [0, 0] && magic[4..15] == *b"\x0a\x09OSMHeader" {
+            return Ok(Self::PBF);
+        }
+
+        let text = magic.strip_prefix(b"\xef\xbb\xbf").unwrap_or(magic);
+        match text.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'<') => Ok(Self::XML),
+            _ => Err(false),
+        }
+    }
+}
+
+/// Tags the read errors of a decompressor, so they are reported as such
+struct Decompressor<R>(R);
+
+impl<R: Read> Read for Decompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::Interrupted => e,
+            kind => io::Error::new(kind, DecompressionError(e)),
+        })
+    }
+}
+
+impl FromStr for FileFormats {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xml" | "osm" => Ok(Self::XML),
+            "bz2" | "bzip2" => Ok(Self::BZIP2),
+            #[cfg(feature = "gzip")]
+            "gz" | "gzip" => Ok(Self::GZIP),
+            #[cfg(feature = "zstd")]
+            "zst" | "zstd" => Ok(Self::ZSTD),
+            #[cfg(feature = "xz")]
+            "xz" => Ok(Self::XZ),
+            "pbf" => Ok(Self::PBF),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
+
+impl FileFormats {
+    /// Short name of the format, as accepted by [`FromStr`]
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormats::XML => "xml",
+            FileFormats::BZIP2 => "bz2",
+            #[cfg(feature = "gzip")]
+            FileFormats::GZIP => "gz",
+            #[cfg(feature = "zstd")]
+            FileFormats::ZSTD => "zst",
+            #[cfg(feature = "xz")]
+            FileFormats::XZ => "xz",
+            FileFormats::PBF => "pbf",
+        }
+    }
+
+    /// Recognise the format of `input` by its first bytes, falling back on
+    /// the extension of `path`
+    ///
+    /// The first bytes are read ahead, which works on pipes as well; the
+    /// returned reader yields the complete input again.
+    pub fn detect<R: Read>(
+        mut input: R,
+        path: Option<&Path>,
+    ) -> io::Result<(Option<Self>, DetectedInput<R>)> {
+        let mut magic = Vec::with_capacity(MAGIC_LENGTH);
+        (&mut input)
+            .take(MAGIC_LENGTH as u64)
+            .read_to_end(&mut magic)?;
+
+        let format = Self::try_from(&magic[..])
+            .or_else(|_| path.map_or(Err(false), |path| Self::try_from(path.as_os_str())))
+            .ok();
+
+        Ok((format, BufReader::new(Cursor::new(magic).chain(input))))
+    }
+
+    /// Wrap `input` in the decoder that matches this format
+    ///
+    /// bzip2 data is decompressed on all available cores.
+    pub fn decode<R: BufRead + Send + 'static>(self, input: R) -> io::Result<Box<dyn BufRead>> {
+        let buf_reader: Box<dyn BufRead> = match self {
+            FileFormats::XML | FileFormats::PBF => Box::new(input),
+
+            FileFormats::BZIP2 => match thread::available_parallelism().map_or(1, |n| n.get()) {
+                1 => Box::new(BufReader::new(Decompressor(
+                    bzip2::bufread::MultiBzDecoder::new(input),
+                ))),
+                threads => Box::new(BufReader::new(Decompressor(ParallelBzDecoder::new(
+                    input, threads,
+                )))),
+            },
+
+            #[cfg(feature = "gzip")]
+            FileFormats::GZIP => {
+                let decompressor = flate2::bufread::MultiGzDecoder::new(input);
+
+                Box::new(BufReader::new(Decompressor(decompressor)))
+            }
+
+            #[cfg(feature = "zstd")]
+            FileFormats::ZSTD => {
+                let decompressor = zstd::stream::read::Decoder::with_buffer(input)?;
+
+                Box::new(BufReader::new(Decompressor(decompressor)))
+            }
+
+            #[cfg(feature = "xz")]
+            FileFormats::XZ => {
+                let decompressor = xz2::bufread::XzDecoder::new_multi_decoder(input);
+
+                Box::new(BufReader::new(Decompressor(decompressor)))
+            }
+        };
+
+        Ok(buf_reader)
+    }
+
+    /// Decode `input` with the parser that matches this format
+    pub fn reader<R: BufRead + Send + 'static>(
+        self,
+        input: R,
+    ) -> io::Result<OsmReader<Box<dyn BufRead>>> {
+        Ok(self.parser(self.decode(input)?))
+    }
+
+    /// Parse already decoded input with the parser that matches this format
+    pub fn parser<R: BufRead>(self, decoded: R) -> OsmReader<R> {
+        match self {
+            FileFormats::PBF => OsmReader::pbf(decoded),
+            _ => OsmReader::new(decoded),
+        }
+    }
+
+    /// Open `path` with the parser that matches its content
+    ///
+    /// Returns `Ok(None)` when the format can be recognised neither from
+    /// the content nor from the extension.
+    pub fn open(path: &Path) -> io::Result<Option<OsmReader<Box<dyn BufRead>>>> {
+        let input = File::open(path)?;
+
+        match Self::detect(input, Some(path))? {
+            (Some(format), input) => format.reader(input).map(Some),
+            (None, _) => Ok(None),
+        }
+    }
+
+    /// File name extensions recognised by this build
+    pub fn extensions() -> Vec<&'static str> {
+        let mut extensions = vec![".osm", ".osm.bz2", ".osc", ".osc.bz2", ".osh", ".osh.bz2"];
+        #[cfg(feature = "gzip")]
+        extensions.extend([".osm.gz", ".osc.gz", ".osh.gz"]);
+        #[cfg(feature = "zstd")]
+        extensions.extend([".osm.zst", ".osc.zst", ".osh.zst"]);
+        #[cfg(feature = "xz")]
+        extensions.extend([".osm.xz", ".osc.xz", ".osh.xz"]);
+        extensions.extend([".osm.pbf", ".osh.pbf"]);
+
+        extensions
+    }
+}
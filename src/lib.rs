@@ -0,0 +1,44 @@
+//! Open Streetmap XML and PBF file parser
+//!
+//! ```no_run
+//! use osm_parse::FileFormats;
+//! use std::path::Path;
+//!
+//! let path = Path::new("extract.osm.bz2");
+//! let mut reader = FileFormats::open(path).unwrap().expect("an OSM file");
+//!
+//! for element in reader.elements() {
+//!     println!("{:?}", element.unwrap());
+//! }
+//! ```
+
+mod attributes;
+mod counter;
+mod element;
+mod error;
+mod expression;
+mod history;
+mod input;
+mod parallel;
+mod parallel_bz2;
+mod pbf;
+mod protobuf;
+mod reader;
+mod stats;
+mod tag_stats;
+mod writer;
+mod xml;
+
+pub use attributes::parse_timestamp;
+pub use counter::{CountingReader, TimedReader};
+pub use element::{Action, Change, Element, Member, Meta, Node, OsmTag, Relation, Tags, Way};
+pub use error::{ExpressionError, OsmParseError, Position, StructureIssue};
+pub use expression::{Expression, TagTest};
+pub use history::{History, HistoryInfo, Snapshot};
+pub use input::{DetectedInput, FileFormats};
+pub use parallel::{parse_parallel, parse_parallel_fold};
+pub use parallel_bz2::ParallelBzDecoder;
+pub use reader::{ChangeElements, Elements, OsmReader, OsmSliceReader, SliceElements};
+pub use stats::{Changes, Info, OtherInfo, OtherTags, Statistics, TagInfo};
+pub use tag_stats::{KeyInfo, TagStatistics, TopValues};
+pub use writer::OsmWriter;
@@ -1,152 +1,553 @@
-#![allow(dead_code)]
-
 use std::{
-    collections::{HashMap, HashSet},
-    convert::TryFrom,
-    ffi::OsStr,
-    fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use bzip2::read::BzDecoder;
-use quick_xml::{events::Event, Reader};
-use structopt::StructOpt;
-
-#[derive(PartialEq, Eq, Hash, Debug)]
-enum OsmTag {
-    Node,
-    Way,
-    Relation,
-}
+use osm_parse::{
+    parse_parallel_fold, CountingReader, Element, FileFormats, History, OsmParseError,
+    OsmSliceReader, TagStatistics, TimedReader,
+};
+use serde_json::json;
+use structopt::{
+    clap::{self, AppSettings},
+    StructOpt,
+};
 
-enum FileFormats {
-    XML,
-    BZIP2,
-}
+mod apply;
+mod bench;
+mod filter;
+mod output;
+mod progress;
+mod report;
+mod snapshot;
 
-#[derive(Default, Debug)]
-struct TagInfo {
-    starts: u64,
-    ends: u64,
-}
+use apply::ApplyOptions;
+use bench::BenchOptions;
+use filter::FilterOptions;
+use progress::{Counters, ProgressLine};
+use report::{OutputFormat, Report};
+use snapshot::SnapshotOptions;
 
 /// Parse an OSM data file
 ///    The data file may be either plain XML (.osm),
-///    or archived (.osm.bz2)
+///    archived (.osm.bz2, .osm.gz, .osm.zst, .osm.xz)
+///    or PBF (.osm.pbf). OsmChange files (.osc) and full-history
+///    files (.osh), archived the same way, are read as well.
 ///
-///    It reports the number of Node, Way and relation tags.
+///    The format is recognised by content, then by extension. Plain
+///    XML files are memory mapped and tokenised in place.
+///
+///    It reports the number of Node, Way and relation tags, and for
+///    OsmChange files how many of them are created, modified or
+///    deleted; with several files or a glob pattern, for each file and
+///    in total, even when the pattern matches a single file. For
+///    full-history files, or with --history, it also reports the
+///    objects, versions and deletions; see the snapshot subcommand for
+///    the data as of a point in time. With --tag-stats, it reports how
+///    often each tag key occurs, along with its most frequent values;
+///    value counts are estimates, as only a few candidates are tracked
+///    per key.
+///
+///    While parsing, a progress line is shown on standard error when it
+///    is a terminal.
+///
+///    Elements that are not closed, closed by the wrong end tag, or
+///    nested in the wrong place are reported on standard error.
+///
+///    Exit codes: 1 invalid arguments, 2 I/O error,
+///    3 decompression error, 4 XML syntax error, 5 PBF error,
+///    6 bad attribute, 7 unbalanced elements (with --strict),
+///    8 benchmark regression, 9 no files match a pattern,
+///    10 unsorted base file (apply-changes), 11 unrecognised format.
+///    With several files, the exit code is that of the first file that
+///    failed.
 ///
 ///    Note: Parsing an archived file takes factors (~4x)
-///          longer than a plan XML file.
+///          longer than a plan XML file; bzip2 archives
+///          are decompressed on all available cores.
 #[derive(StructOpt, Debug)]
 // #[structopt(name = "osm")]
+#[structopt(setting = AppSettings::ArgsNegateSubcommands)]
 struct Options {
-    /// File to process (either .osm or .osm.bz2 extension)
+    /// Files to process (.osm, .osc, .osm.pbf or a compressed extension),
+    /// or - to read from standard input; glob patterns such as
+    /// 'extracts/*.osm.pbf' are expanded
     #[structopt(parse(from_os_str))]
-    file: PathBuf,
-}
+    files: Vec<PathBuf>,
 
-type Info = HashMap<OsmTag, TagInfo>;
-type OtherTags = HashSet<String>;
+    /// Process up to this many files at the same time
+    #[structopt(long, default_value = "1")]
+    concurrency: usize,
 
-impl TryFrom<&[u8]> for OsmTag {
-    type Error = bool;
+    #[structopt(flatten)]
+    input: InputOptions,
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if value.eq_ignore_ascii_case(b"node") {
-            return Ok(Self::Node);
-        } else if value.eq_ignore_ascii_case(b"way") {
-            return Ok(Self::Way);
-        } else if value.eq_ignore_ascii_case(b"relation") {
-            return Ok(Self::Relation);
-        }
+    /// Report layout: text, or json for a stable, machine-readable schema
+    #[structopt(long, default_value = "text")]
+    output: OutputFormat,
 
-        Err(false)
-    }
+    /// Exit with an error when elements are unbalanced or badly nested,
+    /// as in truncated downloads
+    #[structopt(long)]
+    strict: bool,
+
+    /// Do not show the progress line, which is only shown when standard
+    /// error is a terminal
+    #[structopt(long)]
+    no_progress: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-impl TryFrom<&OsStr> for FileFormats {
-    type Error = bool;
+// How to read the input and what to count; not a doc comment, which would
+// become the about text of the commands it is flattened into
+#[derive(StructOpt, Debug, Clone, Copy)]
+struct InputOptions {
+    /// Override format detection (xml, bz2, gz, zst, xz or pbf)
+    #[structopt(long)]
+    format: Option<FileFormats>,
 
-    fn try_from(os_str: &OsStr) -> Result<Self, Self::Error> {
-        if let Some(s) = os_str.to_str() {
-            if s.ends_with("osm.bz2") {
-                return Ok(Self::BZIP2);
-            } else if s.ends_with("osm") {
-                return Ok(Self::XML);
-            }
-        }
+    /// Parse XML on this many threads, splitting the input
+    /// at element boundaries
+    #[structopt(short, long, default_value = "1")]
+    jobs: usize,
 
-        Err(false)
-    }
+    /// Read plain XML files instead of memory mapping them
+    #[cfg(feature = "mmap")]
+    #[structopt(long)]
+    no_mmap: bool,
+
+    /// Count the versions of each object, as in full-history files; on
+    /// for .osh files, which are then parsed on a single thread
+    #[structopt(long)]
+    history: bool,
+
+    /// Count every tag key, and the most frequent values of each key
+    #[structopt(long)]
+    tag_stats: bool,
+
+    /// Number of most frequent values reported per key with --tag-stats
+    #[structopt(long, default_value = "10")]
+    top: usize,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    Bench(BenchOptions),
+    ApplyChanges(ApplyOptions),
+    Snapshot(SnapshotOptions),
+    Filter(FilterOptions),
 }
 
+/// Exit code when the input format cannot be recognised
+const EXIT_UNRECOGNISED_FORMAT: i32 = 11;
+/// Input buffer size, large enough to make timing the reads cheap
+const TIMED_BUFFER_SIZE: usize = 1 << 16;
+/// Exit code for structure issues in strict mode
+const EXIT_UNBALANCED: i32 = 7;
+/// Exit code when a glob pattern matches no files
+const EXIT_NO_FILES: i32 = 9;
+
 fn main() {
     let options = Options::from_args();
-    if let Ok(file_format) = FileFormats::try_from(options.file.as_os_str()) {
-        let input_file = File::open(&options.file).expect("Open XML file");
 
-        let mut reader: Reader<Box<dyn BufRead>> = match file_format {
-            FileFormats::XML => {
-                let buf_reader = Box::new(BufReader::new(input_file));
-                Reader::from_reader(buf_reader)
-            }
+    match &options.command {
+        Some(Command::Bench(bench_options)) => process::exit(bench::run(bench_options)),
+        Some(Command::ApplyChanges(apply_options)) => process::exit(apply::run(apply_options)),
+        Some(Command::Snapshot(snapshot_options)) => process::exit(snapshot::run(snapshot_options)),
+        Some(Command::Filter(filter_options)) => process::exit(filter::run(filter_options)),
+        None => (),
+    }
+    if options.files.is_empty() {
+        clap::Error::with_description(
+            "a file to process, or a subcommand, is required",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+
+    let files = match expand_globs(&options.files) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(EXIT_NO_FILES);
+        }
+    };
 
-            FileFormats::BZIP2 => {
-                let decompressor = BzDecoder::new(input_file);
+    // The layout of the report must not depend on how many files a
+    // pattern happens to match
+    let several =
+        options.files.len() > 1 || options.files.iter().any(|arg| glob_pattern(arg).is_some());
 
-                let buf_reader = Box::new(BufReader::new(decompressor));
-                Reader::from_reader(buf_reader)
+    let started = Instant::now();
+    let results = parse_files(&files, &options);
+    let elapsed = started.elapsed();
+
+    let mut exit = 0;
+    let mut reports = Vec::with_capacity(files.len());
+    let mut files_json = Vec::with_capacity(files.len());
+    for (file, result) in files.iter().zip(results) {
+        let (code, report, error) = match result {
+            Ok(Some(report)) => {
+                for issue in &report.statistics.issues {
+                    eprintln!("{}: {}", file.display(), issue);
+                }
+                match options.strict && !report.statistics.issues.is_empty() {
+                    true => (EXIT_UNBALANCED, Some(report), None),
+                    false => (0, Some(report), None),
+                }
+            }
+            Ok(None) => {
+                let message = unrecognised_message();
+                eprintln!("{}: {}", file.display(), message);
+                (EXIT_UNRECOGNISED_FORMAT, None, Some(message))
+            }
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                (exit_code(&e), None, Some(e.to_string()))
             }
         };
+        if exit == 0 {
+            exit = code;
+        }
 
-        let mut info: Info = HashMap::new();
-        let mut others = HashSet::new();
-
-        let mut register_tag = |add: bool, tag: &[u8]| {
-            let osm_tag = OsmTag::try_from(tag);
-            match osm_tag {
-                Ok(tag) => {
-                    let info_entry = info.entry(tag).or_default();
-                    match add {
-                        true => info_entry.starts += 1,
-                        false => info_entry.ends += 1,
-                    }
-                }
-                Err(_) => {
-                    let tag_name = String::from_utf8_lossy(tag).to_string();
-                    others.insert(tag_name);
+        match (report, options.output) {
+            (Some(report), OutputFormat::Text) => {
+                if several {
+                    println!("==> {} <==", file.display());
                 }
+                report.print(OutputFormat::Text);
+                reports.push(report);
+            }
+            (Some(report), OutputFormat::Json) => {
+                files_json.push(report.to_json());
+                reports.push(report);
+            }
+            (None, _) => files_json.push(json!({
+                "input": { "path": file.display().to_string() },
+                "error": error,
+            })),
+        }
+    }
+
+    match (several, options.output) {
+        (false, OutputFormat::Json) => {
+            if let Some(report) = reports.first() {
+                report.print(OutputFormat::Json);
+            }
+        }
+        (false, OutputFormat::Text) => (),
+        (true, OutputFormat::Text) => {
+            println!("==> total <==");
+            Report::total(reports, elapsed).print(OutputFormat::Text);
+        }
+        (true, OutputFormat::Json) => {
+            let count = reports.len();
+            let mut total = Report::total(reports, elapsed).to_json();
+            if let Some(total) = total.as_object_mut() {
+                total.remove("schema_version");
+                total.remove("issues");
+                total.insert("input".to_string(), json!({ "files": count }));
+            }
+            println!(
+                "{:#}",
+                json!({
+                    "schema_version": report::SCHEMA_VERSION,
+                    "files": files_json,
+                    "total": total,
+                })
+            );
+        }
+    }
+
+    process::exit(exit);
+}
+
+/// Expand the arguments that are glob patterns, keeping the others as
+/// they are
+fn expand_globs(args: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::with_capacity(args.len());
+
+    for arg in args {
+        let pattern = match glob_pattern(arg) {
+            Some(pattern) => pattern,
+            None => {
+                files.push(arg.clone());
+                continue;
             }
         };
 
-        let mut buf = Vec::new();
+        let matches = glob::glob(pattern).map_err(|e| format!("{}: {}", pattern, e))?;
+        let first = files.len();
+        for path in matches {
+            files.push(path.map_err(|e| e.to_string())?);
+        }
+        if files.len() == first {
+            return Err(format!("{}: no files match", pattern));
+        }
+    }
 
-        loop {
-            buf.clear();
-            match reader.read_event_into(&mut buf).unwrap() {
-                Event::Eof => break,
+    Ok(files)
+}
 
-                Event::Start(bytes) => {
-                    register_tag(true, bytes.name().local_name().as_ref());
-                }
+/// The argument as a glob pattern, if it is one
+fn glob_pattern(arg: &Path) -> Option<&str> {
+    arg.to_str()
+        .filter(|pattern| pattern.contains(['*', '?', '[']))
+}
 
-                Event::Empty(bytes) => {
-                    register_tag(true, bytes.name().local_name().as_ref());
-                    register_tag(false, bytes.name().local_name().as_ref());
-                }
+/// Parse every file, on up to `--concurrency` threads; the results are
+/// in the order of `files`
+fn parse_files(files: &[PathBuf], options: &Options) -> Vec<Result<Option<Report>, OsmParseError>> {
+    let threads = options.concurrency.clamp(1, files.len().max(1));
+    // Several progress lines would overwrite each other
+    let progress = !options.no_progress && threads == 1;
+
+    if threads == 1 {
+        return files
+            .iter()
+            .map(|file| parse_file(file, &options.input, progress))
+            .collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Vec<_> = files.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let file = match files.get(index) {
+                    Some(file) => file,
+                    None => break,
+                };
+                let result = parse_file(file, &options.input, progress);
+                *results[index].lock().expect("no thread panicked") = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| {
+            result
+                .into_inner()
+                .expect("no thread panicked")
+                .expect("every file was parsed")
+        })
+        .collect()
+}
+
+fn parse_file(
+    file: &Path,
+    options: &InputOptions,
+    progress: bool,
+) -> Result<Option<Report>, OsmParseError> {
+    let (input, path) = open(file)?;
+    parse(input, path, options, progress)
+}
+
+fn unrecognised_message() -> String {
+    format!(
+        "Unrecognised file format; use --format or one of the extensions {}.",
+        FileFormats::extensions().join(", ")
+    )
+}
+
+fn exit_unrecognised() -> ! {
+    eprintln!("{}", unrecognised_message());
+    process::exit(EXIT_UNRECOGNISED_FORMAT);
+}
+
+fn exit_with_error(file: &Path, e: &OsmParseError) -> ! {
+    eprintln!("{}: {}", file.display(), e);
+    process::exit(exit_code(e));
+}
+
+/// Open `file`, or standard input for -, along with the path to detect
+/// its format by
+fn open(file: &Path) -> io::Result<(Box<dyn Read + Send>, Option<&Path>)> {
+    if file == Path::new("-") {
+        Ok((Box::new(io::stdin()), None))
+    } else {
+        Ok((Box::new(File::open(file)?), Some(file)))
+    }
+}
+
+/// Parse the input, or return `None` when its format is not recognised
+///
+/// With `progress`, a progress line is shown while parsing.
+fn parse(
+    input: Box<dyn Read + Send>,
+    path: Option<&Path>,
+    options: &InputOptions,
+    progress: bool,
+) -> Result<Option<Report>, OsmParseError> {
+    let started = Instant::now();
 
-                Event::End(bytes) => {
-                    register_tag(false, bytes.name().local_name().as_ref());
+    let bytes_read = Arc::new(AtomicU64::new(0));
+    let input = CountingReader::new(input, Arc::clone(&bytes_read));
+    let (detected, input) = FileFormats::detect(input, path)?;
+    let file_format = match options.format.or(detected) {
+        Some(file_format) => file_format,
+        None => return Ok(None),
+    };
+
+    let counters = Arc::new(Counters::default());
+    let progress_line = match progress {
+        true => {
+            let size = path
+                .and_then(|path| fs::metadata(path).ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len());
+            ProgressLine::start(Arc::clone(&bytes_read), size, Arc::clone(&counters))
+        }
+        false => None,
+    };
+
+    let bytes_processed = Arc::new(AtomicU64::new(0));
+    let decompression_nanos = Arc::new(AtomicU64::new(0));
+    let count_history = options.history || path.is_some_and(is_history);
+    let mut history = count_history.then(History::default);
+    let mut tag_statistics = options.tag_stats.then(|| TagStatistics::new(options.top));
+    let mut count = |element: Element| {
+        counters.count(element.tag());
+        if let Some(history) = &mut history {
+            history.count(&element);
+        }
+        if let Some(tag_statistics) = &mut tag_statistics {
+            tag_statistics.count(&element);
+        }
+    };
+
+    let map = memory_map(path, file_format, options)?;
+    let mmap = map.is_some();
+    let statistics = if let Some(map) = map {
+        let mut reader = OsmSliceReader::new(&map[..]);
+        while let Some(element) = reader.elements().next() {
+            count(element?);
+            bytes_read.store(reader.position().offset, Ordering::Relaxed);
+        }
+        bytes_read.store(map.len() as u64, Ordering::Relaxed);
+        bytes_processed.store(map.len() as u64, Ordering::Relaxed);
+
+        reader.into_statistics()
+    } else {
+        let input = CountingReader::new(
+            BufReader::with_capacity(
+                TIMED_BUFFER_SIZE,
+                TimedReader::new(file_format.decode(input)?, Arc::clone(&decompression_nanos)),
+            ),
+            Arc::clone(&bytes_processed),
+        );
+
+        // Versions of an object must be counted in order
+        if options.jobs > 1 && file_format != FileFormats::PBF && !count_history {
+            // Each worker counts tags of its own, merged once it is done
+            let top = tag_statistics.as_ref().map(TagStatistics::top);
+            let (statistics, worker_tag_statistics) = parse_parallel_fold(
+                input,
+                options.jobs,
+                || top.map(TagStatistics::new),
+                |worker_tag_statistics, element| {
+                    counters.count(element.tag());
+                    if let Some(worker_tag_statistics) = worker_tag_statistics {
+                        worker_tag_statistics.count(&element);
+                    }
+                },
+            )?;
+            if let Some(tag_statistics) = &mut tag_statistics {
+                for worker_tag_statistics in worker_tag_statistics.into_iter().flatten() {
+                    tag_statistics.merge(worker_tag_statistics);
                 }
+            }
 
-                _ => (),
+            statistics
+        } else {
+            let mut reader = file_format.parser(input);
+            for element in reader.elements() {
+                count(element?);
             }
+
+            reader.into_statistics()
         }
+    };
+    drop(progress_line);
 
-        println!("... and done! \n\tinfo: {:?}\n\tOthers: {:?}", info, others);
-    } else {
-        println!("Only files with extension .osm or .osm.bz2 are supported.");
+    Ok(Some(Report {
+        path: path.map(Path::to_path_buf),
+        format: Some(file_format),
+        statistics,
+        history,
+        tag_statistics,
+        mmap,
+        bytes_read: bytes_read.load(Ordering::Relaxed),
+        bytes_processed: bytes_processed.load(Ordering::Relaxed),
+        elapsed: started.elapsed(),
+        decompression: Duration::from_nanos(decompression_nanos.load(Ordering::Relaxed)),
+    }))
+}
+
+/// Whether `path` is named as a full-history file, such as
+/// `history.osh.bz2`
+fn is_history(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    name.ends_with(".osh") || name.contains(".osh.")
+}
+
+/// Memory map `path` when it is a plain XML file to be parsed on a single
+/// thread, so it is tokenised in place
+#[cfg(feature = "mmap")]
+fn memory_map(
+    path: Option<&Path>,
+    file_format: FileFormats,
+    options: &InputOptions,
+) -> io::Result<Option<memmap2::Mmap>> {
+    let path = match path {
+        Some(path) if file_format == FileFormats::XML && options.jobs <= 1 && !options.no_mmap => {
+            path
+        }
+        _ => return Ok(None),
+    };
+
+    let file = File::open(path)?;
+    if !file.metadata()?.is_file() {
+        return Ok(None);
+    }
+
+    // Safety: the map is only read; as with any mapped file, changing the
+    // file while it is parsed is not supported
+    unsafe { memmap2::Mmap::map(&file) }.map(Some)
+}
+
+/// Without memory mapping, files are always read
+#[cfg(not(feature = "mmap"))]
+fn memory_map(
+    _path: Option<&Path>,
+    _file_format: FileFormats,
+    _options: &InputOptions,
+) -> io::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+/// Distinct exit code per kind of error
+fn exit_code(e: &OsmParseError) -> i32 {
+    match e {
+        OsmParseError::Io { .. } => 2,
+        OsmParseError::Decompression { .. } => 3,
+        OsmParseError::Xml { .. } => 4,
+        OsmParseError::Pbf { .. } => 5,
+        OsmParseError::MissingAttribute { .. } | OsmParseError::BadAttribute { .. } => 6,
     }
 }
@@ -0,0 +1,72 @@
+//! Output files of the subcommands
+//!
+//! An output file is written under a temporary name in the same directory
+//! and only renamed into place once it is complete, so a failure never
+//! leaves an existing file truncated or half written.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::exit_with_error;
+
+/// Destination of a subcommand's result: a file, or standard output
+pub struct Output {
+    /// The file to write, and the temporary file written instead
+    paths: Option<(PathBuf, PathBuf)>,
+}
+
+impl Output {
+    /// Create the temporary file for `path`, or write to standard output
+    /// when there is none
+    pub fn create(path: Option<&Path>) -> (Self, Box<dyn Write>) {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                return (
+                    Self { paths: None },
+                    Box::new(BufWriter::new(io::stdout().lock())),
+                )
+            }
+        };
+
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or(path.as_os_str()));
+        name.push(format!(".{}.tmp", process::id()));
+        let temporary = path.with_file_name(name);
+        match File::create(&temporary) {
+            Ok(file) => (
+                Self {
+                    paths: Some((path.to_path_buf(), temporary)),
+                },
+                Box::new(BufWriter::new(file)),
+            ),
+            Err(e) => exit_with_error(path, &e.into()),
+        }
+    }
+
+    /// Move the output into place when `result` is a success, or remove it
+    ///
+    /// The writer must be flushed and dropped by then.
+    pub fn finish<T, E>(self, result: Result<T, E>) -> Result<T, E> {
+        let (path, temporary) = match self.paths {
+            Some(paths) => paths,
+            None => return result,
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&temporary);
+            return result;
+        }
+
+        if let Err(e) = fs::rename(&temporary, &path) {
+            let _ = fs::remove_file(&temporary);
+            exit_with_error(&path, &e.into());
+        }
+
+        result
+    }
+}
@@ -0,0 +1,524 @@
+//! Parallel parsing of OSM XML
+//!
+//! Top-level elements are independent, so the decompressed input is cut
+//! into chunks right after a `</node>`, `</way>` or `</relation>` end tag,
+//! or a self-closing `<node/>`, `<way/>` or `<relation/>`.
+//! Each chunk is parsed on a worker thread and the per-thread statistics
+//! are merged when all chunks are done. Elements that a chunk starts at its
+//! own top level are attributed to their parent afterwards, and end tags of
+//! elements opened in earlier chunks are matched, going through the chunks
+//! in document order.
+
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::sync_channel,
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    xml::{count_lines, Fragment, XmlParser},
+    Action, Element, OsmParseError, OsmTag, Position, Statistics, StructureIssue,
+};
+
+/// Approximate size of the chunks handed to the worker threads
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size beyond which a chunk without an element boundary is an error,
+/// rather than read further
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+const BOUNDARIES: [&[u8]; 3] = [b"</node", b"</way", b"</relation"];
+const START_TAGS: [&[u8]; 3] = [b"<node", b"<way", b"<relation"];
+
+/// Parse the OSM XML `input` on `threads` worker threads
+///
+/// Every element is passed to `visit`, from whichever thread parsed it,
+/// so elements are not visited in document order.
+pub fn parse_parallel<R, F>(input: R, threads: usize, visit: F) -> Result<Statistics, OsmParseError>
+where
+    R: Read,
+    F: Fn(Element) + Sync,
+{
+    parse_parallel_fold(input, threads, || (), |_, element| visit(element))
+        .map(|(statistics, _)| statistics)
+}
+
+/// Parse the OSM XML `input` on `threads` worker threads, each folding
+/// the elements it parses into a state of its own
+///
+/// Every worker starts from a state made by `init` and passes it to
+/// `fold` along with each element, so no lock is taken per element. The
+/// states of all workers are returned, to be combined by the caller.
+pub fn parse_parallel_fold<R, S, I, F>(
+    input: R,
+    threads: usize,
+    init: I,
+    fold: F,
+) -> Result<(Statistics, Vec<S>), OsmParseError>
+where
+    R: Read,
+    S: Send,
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, Element) + Sync,
+{
+    parse_chunks(input, threads, (CHUNK_SIZE, MAX_CHUNK_SIZE), init, fold)
+}
+
+/// Parse `input` in chunks of about the first of `chunk_sizes`, and at
+/// most the second
+fn parse_chunks<R, S, I, F>(
+    input: R,
+    threads: usize,
+    chunk_sizes: (usize, usize),
+    init: I,
+    fold: F,
+) -> Result<(Statistics, Vec<S>), OsmParseError>
+where
+    R: Read,
+    S: Send,
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, Element) + Sync,
+{
+    let threads = threads.max(1);
+    let (sender, receiver) = sync_channel::<(usize, Vec<u8>, Position)>(threads);
+    // Taken by the first worker to fail, so `send` fails instead of
+    // blocking once no worker is left to receive
+    let receiver = Mutex::new(Some(receiver));
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut statistics = Statistics::default();
+                    let mut fragments = Vec::new();
+                    let mut state = init();
+                    loop {
+                        let next = match receiver.lock() {
+                            Ok(chunks) => chunks.as_ref().map(|chunks| chunks.recv()),
+                            Err(_) => None,
+                        };
+                        let (index, chunk, start) = match next {
+                            Some(Ok(chunk)) => chunk,
+                            _ => return Ok((statistics, fragments, state)),
+                        };
+
+                        let mut parser = XmlParser::fragment(&chunk[..], start);
+                        loop {
+                            if failed.load(Ordering::Relaxed) {
+                                return Ok((statistics, fragments, state));
+                            }
+                            match parser.next_element(&mut statistics) {
+                                Ok(Some(change)) => fold(&mut state, change.element),
+                                Ok(None) => break,
+                                Err(e) => {
+                                    failed.store(true, Ordering::Relaxed);
+                                    if let Ok(mut chunks) = receiver.lock() {
+                                        chunks.take();
+                                    }
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        fragments.push((index, parser.into_fragment()));
+                    }
+                })
+            })
+            .collect();
+
+        let mut index = 0;
+        let read_result = split(input, chunk_sizes, |chunk, start| {
+            index += 1;
+            !failed.load(Ordering::Relaxed) && sender.send((index, chunk, start)).is_ok()
+        });
+        drop(sender);
+
+        let mut statistics = Statistics::default();
+        let mut fragments = Vec::new();
+        let mut states = Vec::with_capacity(threads);
+        let mut first_error = None;
+        for worker in workers {
+            match worker.join().expect("XML worker thread panicked") {
+                Ok((worker_statistics, worker_fragments, state)) => {
+                    statistics.merge(worker_statistics);
+                    fragments.extend(worker_fragments);
+                    states.push(state);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match (read_result, first_error) {
+            (_, Some(e)) => Err(e),
+            (Err(e), None) => Err(e.into()),
+            (Ok(()), None) => {
+                fragments.sort_unstable_by_key(|(index, _)| *index);
+                resolve_fragments(&mut statistics, fragments.into_iter().map(|(_, f)| f))?;
+                statistics
+                    .issues
+                    .sort_by_key(|issue| issue.position().offset);
+
+                Ok((statistics, states))
+            }
+        }
+    })
+}
+
+/// Count the elements started at the top level of each fragment under the
+/// element and OsmChange action that were open there in the complete
+/// document, and match the end tags of elements opened in earlier fragments
+fn resolve_fragments(
+    statistics: &mut Statistics,
+    fragments: impl Iterator<Item = Fragment>,
+) -> Result<(), OsmParseError> {
+    let mut open: Vec<String> = Vec::new();
+    let mut end = Position::default();
+
+    for fragment in fragments {
+        let mut unresolved = fragment.unresolved.into_iter();
+        let mut closed = fragment.closed.into_iter();
+        let mut positionless = fragment.positionless.into_iter().peekable();
+        for step in 0.. {
+            let action = open
+                .iter()
+                .rev()
+                .find_map(|name| Action::try_from(name.as_bytes()).ok());
+            // Nodes may only lose their position when deleted
+            while let Some((_, error)) = positionless.next_if(|(closed, _)| *closed == step) {
+                if action != Some(Action::Delete) {
+                    return Err(error);
+                }
+            }
+
+            // The document element has no parent
+            if let (Some(counts), Some(parent)) = (unresolved.next(), open.last()) {
+                for (name, count) in counts {
+                    statistics.register_parent(&name, parent, count);
+                    if let (Some(action), Ok(tag)) = (action, OsmTag::try_from(name.as_bytes())) {
+                        statistics.register_change(action, tag, count);
+                    }
+                }
+            }
+
+            let (found, position) = match closed.next() {
+                Some(closed) => closed,
+                None => break,
+            };
+            match open.iter().rposition(|name| *name == found) {
+                Some(depth) => {
+                    for name in open.drain(depth + 1..).rev() {
+                        statistics.issues.push(StructureIssue::Mismatch {
+                            open: name,
+                            found: found.clone(),
+                            position,
+                        });
+                    }
+                    open.pop();
+                }
+                None => statistics
+                    .issues
+                    .push(StructureIssue::UnexpectedEnd { found, position }),
+            }
+        }
+
+        open.extend(fragment.open);
+        end = fragment.end;
+    }
+
+    while let Some(name) = open.pop() {
+        statistics.issues.push(StructureIssue::Unclosed {
+            open: name,
+            position: end,
+        });
+    }
+
+    Ok(())
+}
+
+/// Cut `input` into chunks that end at an element boundary
+///
+/// Chunks are about `chunk_size` bytes, or larger when there is no
+/// boundary, up to `max_chunk_size`. Each chunk is sent along with its
+/// position in `input`; stops early when `send` returns false.
+fn split<R: Read>(
+    mut input: R,
+    (chunk_size, max_chunk_size): (usize, usize),
+    mut send: impl FnMut(Vec<u8>, Position) -> bool,
+) -> io::Result<()> {
+    let mut chunk = Vec::with_capacity(chunk_size * 2);
+    let mut start = Position {
+        offset: 0,
+        line: Some(1),
+    };
+
+    loop {
+        let filled = chunk.len();
+        let read = (&mut input)
+            .take((chunk_size.max(filled) * 2 - filled) as u64)
+            .read_to_end(&mut chunk)?;
+
+        if read == 0 {
+            if !chunk.is_empty() {
+                send(chunk, start);
+            }
+            return Ok(());
+        }
+
+        if let Some(boundary) = last_boundary(&chunk) {
+            let rest = chunk.split_off(boundary);
+            let next = Position {
+                offset: start.offset + chunk.len() as u64,
+                line: start.line.map(|line| line + count_lines(&chunk)),
+            };
+            if !send(chunk, start) {
+                return Ok(());
+            }
+            start = next;
+            chunk = Vec::with_capacity(chunk_size * 2);
+            chunk.extend_from_slice(&rest);
+        } else if chunk.len() > max_chunk_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "no element boundary within {} MB; parse with -j 1",
+                    chunk.len() / 1_000_000
+                ),
+            ));
+        }
+    }
+}
+
+/// Position just after the last top-level end tag, or self-closing
+/// top-level element, in `data`
+fn last_boundary(data: &[u8]) -> Option<usize> {
+    data.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, &b)| b == b'>')
+        .find(|&(index, _)| {
+            BOUNDARIES.iter().any(|tag| data[..index].ends_with(tag))
+                || (data[..index].ends_with(b"/") && ends_empty_element(data, index))
+        })
+        .map(|(index, _)| index + 1)
+}
+
+/// Whether the `/>` ending at `end` closes a self-closing `<node`, `<way`
+/// or `<relation` start tag, rather than another tag or an attribute value
+fn ends_empty_element(data: &[u8], end: usize) -> bool {
+    let start = match data[..end].iter().rposition(|&b| b == b'<') {
+        Some(start) => start,
+        None => return false,
+    };
+    let tag = &data[start..end];
+    let name_end = START_TAGS
+        .iter()
+        .find(|name| tag.starts_with(name))
+        .map(|name| name.len());
+    match name_end.and_then(|name_end| tag.get(name_end)) {
+        Some(b) if b.is_ascii_whitespace() || *b == b'/' => (),
+        _ => return false,
+    }
+
+    // No `>` outside of quotes may end the start tag earlier
+    let mut quote = None;
+    for &b in tag {
+        match (quote, b) {
+            (Some(open), b) if b == open => quote = None,
+            (Some(_), _) => (),
+            (None, b'"' | b'\'') => quote = Some(b),
+            (None, b'>') => return false,
+            (None, _) => (),
+        }
+    }
+
+    quote.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OsmReader;
+
+    /// Statistics of `document`, and the sorted tags and ids of its
+    /// elements
+    type Parsed = Result<(Statistics, Vec<(OsmTag, i64)>), String>;
+
+    fn sequential(document: &str) -> Parsed {
+        let mut reader = OsmReader::new(document.as_bytes());
+        let mut elements = Vec::new();
+        for change in reader.changes() {
+            let element = change.map_err(|e| e.to_string())?.element;
+            elements.push((element.tag(), element.id()));
+        }
+        elements.sort_unstable();
+
+        Ok((reader.into_statistics(), elements))
+    }
+
+    /// Parse `document` on `threads` workers, each collecting the
+    /// elements it parses
+    fn parallel(document: &str, threads: usize, chunk_size: usize) -> Parsed {
+        let (statistics, states) = parse_chunks(
+            document.as_bytes(),
+            threads,
+            (chunk_size, usize::MAX),
+            Vec::new,
+            |elements, element| elements.push((element.tag(), element.id())),
+        )
+        .map_err(|e| e.to_string())?;
+        let mut elements = states.concat();
+        elements.sort_unstable();
+
+        Ok((statistics, elements))
+    }
+
+    /// Parse `document` cut into chunks of every size up to its length,
+    /// and compare with parsing it in one go
+    fn assert_same_as_sequential(document: &str) {
+        let expected = sequential(document);
+        for chunk_size in 1..=document.len() {
+            let parsed = parallel(document, 3, chunk_size);
+            assert_eq!(parsed, expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    fn chunks(document: &str, chunk_size: usize) -> Vec<(String, Position)> {
+        let mut chunks = Vec::new();
+        split(
+            document.as_bytes(),
+            (chunk_size, usize::MAX),
+            |chunk, start| {
+                chunks.push((String::from_utf8(chunk).unwrap(), start));
+                true
+            },
+        )
+        .unwrap();
+
+        chunks
+    }
+
+    #[test]
+    fn boundaries() {
+        let cases: [(&str, Option<usize>); 8] = [
+            ("<node id=\"1\"></node>", Some(20)),
+            ("<way id=\"1\"/> <tag k=\"a\" v=\"b\"/>", Some(13)),
+            ("<relation id=\"1\"/>", Some(18)),
+            ("<node id=\"1\" user=\"a/>b\"/>", Some(26)),
+            ("<node id=\"1\" user='a\"/>'", None),
+            ("<nodes/>", None),
+            ("<node id=\"1\"><tag k=\"a\" v=\"b\"/>", None),
+            ("<osm/>", None),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(last_boundary(data.as_bytes()), expected, "{}", data);
+        }
+    }
+
+    #[test]
+    fn split_at_boundaries() {
+        let document = "<osm>\n<node id=\"1\"/>\n<way id=\"2\">\n<nd ref=\"1\"/>\n</way>\n</osm>\n";
+        let chunks = chunks(document, 1);
+        assert_eq!(
+            chunks,
+            [
+                (
+                    "<osm>\n<node id=\"1\"/>".to_string(),
+                    Position {
+                        offset: 0,
+                        line: Some(1)
+                    }
+                ),
+                (
+                    "\n<way id=\"2\">\n<nd ref=\"1\"/>\n</way>".to_string(),
+                    Position {
+                        offset: 20,
+                        line: Some(2)
+                    }
+                ),
+                (
+                    "\n</osm>\n".to_string(),
+                    Position {
+                        offset: 54,
+                        line: Some(5)
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_limits_chunks() {
+        let document = format!(
+            "<osm><way id=\"1\">{}</way></osm>",
+            "<nd ref=\"1\"/>".repeat(100)
+        );
+        let error = split(document.as_bytes(), (4, 64), |_, _| true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn cut_inside_create() {
+        assert_same_as_sequential(
+            "<osmChange version=\"0.6\">\n\
+             <create>\n\
+             <node id=\"-1\" lat=\"1\" lon=\"2\"/>\n\
+             <node id=\"-2\" lat=\"1\" lon=\"2\"><tag k=\"a\" v=\"b\"/></node>\n\
+             <way id=\"-3\"><nd ref=\"-1\"/><nd ref=\"-2\"/></way>\n\
+             </create>\n\
+             <modify><node id=\"4\" lat=\"1\" lon=\"2\"/></modify>\n\
+             </osmChange>\n",
+        );
+    }
+
+    #[test]
+    fn delete_without_position_after_cut() {
+        assert_same_as_sequential(
+            "<osmChange version=\"0.6\">\n\
+             <modify><node id=\"1\" lat=\"1\" lon=\"2\"/></modify>\n\
+             <delete>\n\
+             <node id=\"2\"/>\n\
+             <node id=\"3\" version=\"2\"/>\n\
+             </delete>\n\
+             </osmChange>\n",
+        );
+    }
+
+    #[test]
+    fn positionless_node_outside_delete() {
+        let document = "<osmChange>\n<delete><node id=\"1\"/></delete>\n\
+                        <modify><node id=\"2\" lat=\"1\" lon=\"2\"/>\n<node id=\"3\"/></modify>\n\
+                        </osmChange>\n";
+        assert!(sequential(document).is_err());
+        assert_same_as_sequential(document);
+    }
+
+    #[test]
+    fn open_across_chunks() {
+        assert_same_as_sequential(
+            "<osm>\n<extra>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n\
+             <way id=\"2\"><nd ref=\"1\"/></way>\n</osm>\n",
+        );
+        assert_same_as_sequential(
+            "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n</extra>\n\
+             <node id=\"2\" lat=\"1\" lon=\"2\"/>\n<bounds>\n",
+        );
+    }
+
+    #[test]
+    fn worker_error() {
+        let node = "<node id=\"1\" lat=\"abc\" lon=\"2\"/>\n";
+        let document = format!("<osm>\n{}</osm>\n", node.repeat(200));
+        let expected = sequential(&document);
+        assert!(expected.is_err());
+        for threads in [1, 2, 4] {
+            let parsed = parallel(&document, threads, 64);
+            assert_eq!(parsed, expected, "{} threads", threads);
+        }
+    }
+}
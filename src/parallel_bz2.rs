@@ -0,0 +1,540 @@
+//! Multi-threaded bzip2 decompression
+//!
+//! Every bzip2 block starts with a 48-bit magic number and can be
+//! decompressed on its own. As blocks are not byte aligned, the input is
+//! scanned bit by bit for the block and end-of-stream magics. Each block
+//! found is wrapped in a single block stream of its own and handed to a
+//! worker thread; the decompressed blocks are returned in input order.
+//!
+//! A block magic may, very rarely, also occur inside compressed data, so
+//! the input is cut into pieces at every magic found, and none of it is
+//! dropped. When a block fails to decompress, the pieces after it are
+//! joined to it one by one and it is decompressed again, as lbzip2 does.
+
+use std::{
+    io::{self, Cursor, Read},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+const MAGIC_MASK: u64 = (1 << 48) - 1;
+const MAGIC_BITS: u64 = 48;
+
+/// Size beyond which a block that fails to decompress is not joined with
+/// the next piece; more than any bzip2 block compresses to
+const MAX_BLOCK_BYTES: usize = 4 << 20;
+
+type BlockResult = io::Result<Vec<u8>>;
+type Job = (Arc<BitWriter>, SyncSender<BlockResult>);
+
+/// The input from one magic up to the next
+struct Piece {
+    bits: Arc<BitWriter>,
+    /// Decompressed data, for pieces that start with a block magic
+    block: Option<Receiver<BlockResult>>,
+}
+
+/// Decompress (multi-stream) bzip2 data on a pool of worker threads
+pub struct ParallelBzDecoder {
+    ordered: Receiver<io::Result<Piece>>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl ParallelBzDecoder {
+    /// Start decompressing `input` on `threads` worker threads
+    pub fn new<R: Read + Send + 'static>(input: R, threads: usize) -> Self {
+        let threads = threads.max(1);
+        let (job_sender, job_receiver) = sync_channel::<Job>(threads);
+        let (ordered_sender, ordered) = sync_channel(threads * 2);
+
+        let jobs = Arc::new(Mutex::new(job_receiver));
+        for _ in 0..threads {
+            let jobs = Arc::clone(&jobs);
+            thread::spawn(move || loop {
+                let job = jobs.lock().map(|jobs| jobs.recv());
+                match job {
+                    Ok(Ok((bits, result))) => {
+                        let _ = result.send(decompress(&bits));
+                    }
+                    _ => break,
+                }
+            });
+        }
+
+        thread::spawn(move || {
+            if let Err(e) = split(input, &job_sender, &ordered_sender) {
+                let _ = ordered_sender.send(Err(e));
+            }
+        });
+
+        Self {
+            ordered,
+            current: Cursor::new(Vec::new()),
+        }
+    }
+
+    /// Decompress a block that was cut short at a magic inside its
+    /// compressed data, by joining the pieces after it until it decompresses
+    fn rejoin(&mut self, bits: &BitWriter, error: io::Error) -> BlockResult {
+        let mut joined = BitWriter::default();
+        joined.push_range(&bits.bytes, 0, bits.bits);
+
+        while joined.bytes.len() <= MAX_BLOCK_BYTES {
+            let next = match self.ordered.recv() {
+                Ok(next) => next?,
+                Err(_) => break,
+            };
+            joined.push_range(&next.bits.bytes, 0, next.bits.bits);
+            if let Ok(data) = decompress(&joined) {
+                return Ok(data);
+            }
+        }
+
+        Err(error)
+    }
+}
+
+impl Read for ParallelBzDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            let piece = match self.ordered.recv() {
+                Ok(piece) => piece?,
+                Err(_) => return Ok(0),
+            };
+            let result = match &piece.block {
+                Some(block) => block
+                    .recv()
+                    .map_err(|_| io::Error::other("bzip2 worker thread stopped"))?,
+                // The end of a stream, and the header of the next one
+                None => continue,
+            };
+            let data = match result {
+                Ok(data) => data,
+                Err(e) => self.rejoin(&piece.bits, e)?,
+            };
+            self.current = Cursor::new(data);
+        }
+    }
+}
+
+/// Cut `input` into pieces at every magic and queue them in order, along
+/// with the blocks for decompression
+fn split<R: Read>(
+    mut input: R,
+    jobs: &SyncSender<Job>,
+    ordered: &SyncSender<io::Result<Piece>>,
+) -> io::Result<()> {
+    let mut chunk = vec![0; 1 << 20];
+    // Bytes from the one holding the start of the current piece onwards
+    let mut buffer: Vec<u8> = Vec::new();
+    // Start of the current piece, and whether it is a block
+    let mut piece_start: Option<(u64, bool)> = None;
+    let mut window = 0u64;
+    let mut seen = 0u64;
+
+    loop {
+        let n = match input.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let first = buffer.len();
+        buffer.extend_from_slice(&chunk[..n]);
+
+        for index in first..buffer.len() {
+            window = window << 8 | u64::from(buffer[index]);
+            seen += 8;
+
+            // Check the eight bit alignments, earliest first
+            for shift in (0..8).rev() {
+                if seen < MAGIC_BITS + shift {
+                    continue;
+                }
+
+                let magic = (window >> shift) & MAGIC_MASK;
+                if magic != BLOCK_MAGIC && magic != END_MAGIC {
+                    continue;
+                }
+
+                let position = (index as u64 + 1) * 8 - shift - MAGIC_BITS;
+                if let Some((start, block)) = piece_start {
+                    if !send_piece(&buffer, start, position, block, jobs, ordered) {
+                        // The reading side has gone away
+                        return Ok(());
+                    }
+                }
+                piece_start = Some((position, magic == BLOCK_MAGIC));
+            }
+        }
+
+        // Keep only what the current piece, or a magic spanning the next
+        // chunk, still needs
+        let keep_from = match piece_start {
+            Some((start, _)) => (start / 8) as usize,
+            None => buffer.len().saturating_sub(8),
+        };
+        buffer.drain(..keep_from);
+        if let Some((start, _)) = piece_start.as_mut() {
+            *start -= keep_from as u64 * 8;
+        }
+    }
+
+    // The last piece may still complete a block cut short before it
+    if let Some((start, block)) = piece_start {
+        let end = buffer.len() as u64 * 8;
+        if !send_piece(&buffer, start, end, false, jobs, ordered) {
+            return Ok(());
+        }
+        if block {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated bzip2 stream",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Queue bits `start..end` of `buffer`, and decompress them when they are
+/// a `block`; false when the reading side has gone away
+fn send_piece(
+    buffer: &[u8],
+    start: u64,
+    end: u64,
+    block: bool,
+    jobs: &SyncSender<Job>,
+    ordered: &SyncSender<io::Result<Piece>>,
+) -> bool {
+    let mut bits = BitWriter::default();
+    bits.push_range(buffer, start, end);
+    let bits = Arc::new(bits);
+
+    let (result, receiver) = match block {
+        true => {
+            let (result, receiver) = sync_channel(1);
+            (Some(result), Some(receiver))
+        }
+        false => (None, None),
+    };
+    let piece = Piece {
+        bits: Arc::clone(&bits),
+        block: receiver,
+    };
+
+    ordered.send(Ok(piece)).is_ok() && result.is_none_or(|result| jobs.send((bits, result)).is_ok())
+}
+
+/// Wrap the block in bits `start..end` of `buffer` in a stream of its own
+fn single_block_stream(buffer: &[u8], start: u64, end: u64) -> Vec<u8> {
+    let mut stream = BitWriter::default();
+    stream.push_bytes(b"BZh9");
+    stream.push_range(buffer, start, end);
+    stream.push(END_MAGIC, 48);
+    // With a single block, the stream CRC equals the block CRC
+    stream.push(read_bits(buffer, start + MAGIC_BITS, 32), 32);
+
+    stream.bytes
+}
+
+/// Decompress the block in `bits`
+fn decompress(bits: &BitWriter) -> BlockResult {
+    let stream = single_block_stream(&bits.bytes, 0, bits.bits);
+    let mut data = Vec::new();
+    bzip2::read::BzDecoder::new(&stream[..]).read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+fn read_bits(buffer: &[u8], start: u64, count: u32) -> u64 {
+    (start..start + u64::from(count)).fold(0, |value, bit| {
+        value << 1 | u64::from(buffer[(bit / 8) as usize] >> (7 - bit % 8) & 1)
+    })
+}
+
+/// Most significant bit first writer, as bzip2 streams are laid out
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= ((value >> bit & 1) as u8) << (7 - self.bits % 8);
+            self.bits += 1;
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(u64::from(byte), 8);
+        }
+    }
+
+    /// Append bits `start..end` of `buffer`
+    fn push_range(&mut self, buffer: &[u8], start: u64, end: u64) {
+        // Whole bytes are copied once the writer is byte aligned
+        let head = ((8 - self.bits % 8) % 8).min(end - start);
+        self.push(read_bits(buffer, start, head as u32), head as u32);
+        let start = start + head;
+
+        let shift = (start % 8) as u32;
+        let first = (start / 8) as usize;
+        let whole_bytes = ((end - start) / 8) as usize;
+
+        self.bytes.reserve(whole_bytes + 1);
+        for index in first..first + whole_bytes {
+            let byte = match shift {
+                0 => buffer[index],
+                _ => buffer[index] << shift | buffer[index + 1] >> (8 - shift),
+            };
+            self.bytes.push(byte);
+        }
+        self.bits += whole_bytes as u64 * 8;
+
+        let rest = start + whole_bytes as u64 * 8;
+        self.push(
+            read_bits(buffer, rest, (end - rest) as u32),
+            (end - rest) as u32,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Text of pseudo-random words, compressible enough to be realistic
+    fn text(len: usize) -> Vec<u8> {
+        const WORDS: [&str; 8] = ["node", "way", "relation", "tag", "k", "v", "ref", "member"];
+        let mut state = 12345u32;
+        let mut text = Vec::with_capacity(len + 16);
+        while text.len() < len {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            text.extend_from_slice(WORDS[(state >> 16) as usize % WORDS.len()].as_bytes());
+            text.extend_from_slice(format!("={} ", state >> 20).as_bytes());
+        }
+        text.truncate(len);
+
+        text
+    }
+
+    /// A stream with 100k blocks
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::new(1));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Cut `input` into pieces, with the blocks decompressed
+    fn pieces(input: &[u8]) -> Vec<(Arc<BitWriter>, Option<BlockResult>)> {
+        let (job_sender, jobs) = sync_channel(1000);
+        let (ordered_sender, ordered) = sync_channel(1000);
+        split(input, &job_sender, &ordered_sender).unwrap();
+        drop((job_sender, ordered_sender));
+
+        let mut jobs = jobs.into_iter();
+        ordered
+            .into_iter()
+            .map(|piece| {
+                let piece = piece.unwrap();
+                let result = piece.block.map(|_| {
+                    let (bits, _) = jobs.next().unwrap();
+                    decompress(&bits)
+                });
+                (piece.bits, result)
+            })
+            .collect()
+    }
+
+    /// A decoder reading the given pieces instead of splitting its input
+    fn decoder(pieces: Vec<(BitWriter, bool)>) -> ParallelBzDecoder {
+        let (sender, ordered) = sync_channel(pieces.len());
+        for (bits, block) in pieces {
+            let receiver = block.then(|| {
+                let (result, receiver) = sync_channel(1);
+                result.send(decompress(&bits)).unwrap();
+                receiver
+            });
+            let piece = Piece {
+                bits: Arc::new(bits),
+                block: receiver,
+            };
+            sender.send(Ok(piece)).unwrap();
+        }
+
+        ParallelBzDecoder {
+            ordered,
+            current: Cursor::new(Vec::new()),
+        }
+    }
+
+    fn bits_of(buffer: &[u8], start: u64, end: u64) -> BitWriter {
+        let mut bits = BitWriter::default();
+        bits.push_range(buffer, start, end);
+        bits
+    }
+
+    #[test]
+    fn push_range_every_alignment() {
+        let buffer = [0xa5, 0x3c, 0xff, 0x00, 0x81, 0x7e, 0x12, 0xed];
+        for prefix in 0..8 {
+            for start in 0..16 {
+                for len in 0..=40 {
+                    let mut writer = BitWriter::default();
+                    writer.push(0b1011_0110 >> (8 - prefix), prefix as u32);
+                    writer.push_range(&buffer, start, start + len);
+
+                    assert_eq!(writer.bits, prefix + len);
+                    assert_eq!(writer.bytes.len() as u64, (prefix + len).div_ceil(8));
+                    for bit in 0..len {
+                        assert_eq!(
+                            read_bits(&writer.bytes, prefix + bit, 1),
+                            read_bits(&buffer, start + bit, 1),
+                            "prefix {} start {} len {} bit {}",
+                            prefix,
+                            start,
+                            len,
+                            bit
+                        );
+                    }
+                    // Padding bits are zero
+                    let padding = writer.bytes.len() as u64 * 8 - writer.bits;
+                    let padding = read_bits(&writer.bytes, writer.bits, padding as u32);
+                    assert_eq!(padding, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_block_stream_every_alignment() {
+        let data = text(5000);
+        let stream = compress(&data);
+        // The block runs from after the header up to the end magic
+        let start = 32;
+        let end = (start..stream.len() as u64 * 8 - MAGIC_BITS)
+            .rev()
+            .find(|&bit| read_bits(&stream, bit, 48) == END_MAGIC)
+            .unwrap();
+
+        for shift in 0..8 {
+            let mut shifted = BitWriter::default();
+            shifted.push(0, shift);
+            shifted.push_range(&stream, 0, stream.len() as u64 * 8);
+
+            let shift = u64::from(shift);
+            let block = single_block_stream(&shifted.bytes, start + shift, end + shift);
+            let mut decompressed = Vec::new();
+            bzip2::read::BzDecoder::new(&block[..])
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn split_blocks_and_streams() {
+        let (first, second) = (text(350_000), text(250_000));
+        let mut input = compress(&first);
+        input.extend(compress(&second));
+
+        let pieces = pieces(&input);
+        let blocks = pieces.iter().filter(|(_, result)| result.is_some()).count();
+        assert!(blocks >= 5, "{} blocks", blocks);
+        assert_eq!(pieces.len() - blocks, 2, "one end per stream");
+
+        let mut decompressed = Vec::new();
+        for (_, result) in pieces {
+            if let Some(result) = result {
+                decompressed.extend(result.unwrap());
+            }
+        }
+        assert_eq!(decompressed, [first, second].concat());
+    }
+
+    #[test]
+    fn parallel_round_trip() {
+        let data = text(450_000);
+        let mut input = compress(&data);
+        input.extend(compress(&data[..1000]));
+        input.extend(compress(b""));
+
+        for threads in [1, 3] {
+            let mut decompressed = Vec::new();
+            ParallelBzDecoder::new(Cursor::new(input.clone()), threads)
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, [&data[..], &data[..1000]].concat());
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let input = compress(&text(250_000));
+        let mut decompressed = Vec::new();
+        let result = ParallelBzDecoder::new(Cursor::new(input[..input.len() / 2].to_vec()), 2)
+            .read_to_end(&mut decompressed);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Cut a block into two pieces, as a magic inside its compressed data
+    /// would, starting the second piece with a block or an end magic
+    #[test]
+    fn false_magic_rejoined() {
+        let data = text(350_000);
+        let input = compress(&data);
+        let pieces = pieces(&input);
+
+        for false_block in [true, false] {
+            let mut cut = Vec::new();
+            for (index, (bits, result)) in pieces.iter().enumerate() {
+                if index == 1 {
+                    let middle = bits.bits / 2;
+                    cut.push((bits_of(&bits.bytes, 0, middle), true));
+                    cut.push((bits_of(&bits.bytes, middle, bits.bits), false_block));
+                } else {
+                    cut.push((bits_of(&bits.bytes, 0, bits.bits), result.is_some()));
+                }
+            }
+
+            let mut decompressed = Vec::new();
+            decoder(cut).read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn corrupt_block() {
+        let input = compress(&text(250_000));
+        let mut corrupt: Vec<_> = pieces(&input)
+            .into_iter()
+            .map(|(bits, result)| (bits_of(&bits.bytes, 0, bits.bits), result.is_some()))
+            .collect();
+        let middle = corrupt[1].0.bytes.len() / 2;
+        corrupt[1].0.bytes[middle] ^= 0x10;
+
+        let mut decompressed = Vec::new();
+        assert!(decoder(corrupt).read_to_end(&mut decompressed).is_err());
+    }
+}
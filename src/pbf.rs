@@ -0,0 +1,724 @@
+//! Decoder for the OSM PBF format
+//!
+//! A PBF file is a sequence of blobs, each preceded by a 4-byte big endian
+//! length and a `BlobHeader`. The first blob holds an `OSMHeader`, the
+//! others hold `OSMData` primitive blocks with the actual elements.
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    convert::TryFrom,
+    io::{self, Read},
+};
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    protobuf::{delta_coded, repeated, zigzag, Fields, Value},
+    Element, Member, Meta, Node, OsmParseError, OsmTag, Position, Relation, Statistics, Tags, Way,
+};
+
+const MAX_BLOB_HEADER_SIZE: u32 = 64 * 1024;
+const MAX_BLOB_SIZE: u32 = 32 * 1024 * 1024;
+
+const SUPPORTED_FEATURES: [&str; 3] = ["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"];
+
+/// Blob based parser for OSM PBF data
+pub(crate) struct PbfParser<R: Read> {
+    input: R,
+    pending: VecDeque<Element>,
+    in_osm: bool,
+    /// Bytes read so far, and the offset of the last blob read
+    offset: u64,
+    blob_offset: u64,
+}
+
+/// Coordinate and timestamp scaling of one primitive block
+struct Block<'a> {
+    strings: Vec<Cow<'a, str>>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+    date_granularity: i64,
+}
+
+impl<R: Read> PbfParser<R> {
+    pub(crate) fn new(input: R) -> Self {
+        Self {
+            input,
+            pending: VecDeque::new(),
+            in_osm: false,
+            offset: 0,
+            blob_offset: 0,
+        }
+    }
+
+    pub(crate) fn next_element(
+        &mut self,
+        statistics: &mut Statistics,
+    ) -> Result<Option<Element>, OsmParseError> {
+        self.read_element(statistics).map_err(|e| {
+            e.at(Position {
+                offset: self.blob_offset,
+                line: None,
+            })
+        })
+    }
+
+    fn read_element(
+        &mut self,
+        statistics: &mut Statistics,
+    ) -> Result<Option<Element>, OsmParseError> {
+        loop {
+            if let Some(element) = self.pending.pop_front() {
+                return Ok(Some(element));
+            }
+
+            match self.read_blob()? {
+                None => {
+                    if self.in_osm {
+                        self.in_osm = false;
+                        statistics.register_tag(false, b"osm", None);
+                    }
+                    return Ok(None);
+                }
+
+                Some((blob_type, data)) => match blob_type.as_str() {
+                    "OSMHeader" => self.read_header(&data, statistics)?,
+                    "OSMData" => self.read_block(&data, statistics)?,
+                    // Unknown blob types must be skipped
+                    _ => (),
+                },
+            }
+        }
+    }
+
+    /// Read the next blob, returning its type and decompressed content
+    fn read_blob(&mut self) -> Result<Option<(String, Vec<u8>)>, OsmParseError> {
+        self.blob_offset = self.offset;
+        let header_size = match self.read_size()? {
+            Some(size) if size > MAX_BLOB_HEADER_SIZE => {
+                return Err(OsmParseError::pbf(format!(
+                    "blob header of {} bytes exceeds the maximum",
+                    size
+                )))
+            }
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        let header = self.read_bytes(header_size)?;
+        let (mut blob_type, mut data_size) = (String::new(), None);
+        for field in Fields::new(&header) {
+            match field? {
+                (1, Value::Bytes(bytes)) => blob_type = String::from_utf8_lossy(bytes).to_string(),
+                (3, Value::Varint(size)) => data_size = Some(size as u32),
+                _ => (),
+            }
+        }
+
+        let data_size =
+            data_size.ok_or_else(|| OsmParseError::pbf("blob header without datasize"))?;
+        if data_size > MAX_BLOB_SIZE {
+            return Err(OsmParseError::pbf(format!(
+                "blob of {} bytes exceeds the maximum",
+                data_size
+            )));
+        }
+
+        let blob = self.read_bytes(data_size)?;
+
+        Ok(Some((blob_type, decode_blob(&blob)?)))
+    }
+
+    fn read_size(&mut self) -> Result<Option<u32>, OsmParseError> {
+        let mut size = [0u8; 4];
+        let mut filled = 0;
+        while filled < size.len() {
+            match self.input.read(&mut size[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(OsmParseError::pbf("truncated blob length")),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.offset += size.len() as u64;
+
+        Ok(Some(u32::from_be_bytes(size)))
+    }
+
+    fn read_bytes(&mut self, size: u32) -> Result<Vec<u8>, OsmParseError> {
+        let mut bytes = vec![0; size as usize];
+        self.input
+            .read_exact(&mut bytes)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => OsmParseError::pbf("truncated blob"),
+                _ => e.into(),
+            })?;
+        self.offset += u64::from(size);
+
+        Ok(bytes)
+    }
+
+    fn read_header(
+        &mut self,
+        data: &[u8],
+        statistics: &mut Statistics,
+    ) -> Result<(), OsmParseError> {
+        let mut has_bbox = false;
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Bytes(_)) => has_bbox = true,
+                (4, Value::Bytes(feature)) => {
+                    let feature = String::from_utf8_lossy(feature);
+                    if !SUPPORTED_FEATURES.contains(&feature.as_ref()) {
+                        return Err(OsmParseError::pbf(format!(
+                            "unsupported required feature {}",
+                            feature
+                        )));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if !self.in_osm {
+            self.in_osm = true;
+            statistics.register_tag(true, b"osm", None);
+        }
+        if has_bbox {
+            statistics.register_tag(true, b"bounds", Some(b"osm"));
+            statistics.register_tag(false, b"bounds", None);
+        }
+
+        Ok(())
+    }
+
+    fn read_block(
+        &mut self,
+        data: &[u8],
+        statistics: &mut Statistics,
+    ) -> Result<(), OsmParseError> {
+        let mut block = Block {
+            strings: Vec::new(),
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+            date_granularity: 1000,
+        };
+        let mut groups = Vec::new();
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Bytes(table)) => {
+                    for field in Fields::new(table) {
+                        if let (1, Value::Bytes(s)) = field? {
+                            block.strings.push(String::from_utf8_lossy(s));
+                        }
+                    }
+                }
+                (2, Value::Bytes(group)) => groups.push(group),
+                (17, Value::Varint(v)) => block.granularity = v as i64,
+                (18, Value::Varint(v)) => block.date_granularity = v as i64,
+                (19, Value::Varint(v)) => block.lat_offset = v as i64,
+                (20, Value::Varint(v)) => block.lon_offset = v as i64,
+                _ => (),
+            }
+        }
+
+        let first = self.pending.len();
+        for group in groups {
+            for field in Fields::new(group) {
+                match field? {
+                    (1, Value::Bytes(node)) => self.pending.push_back(block.node(node)?),
+                    (2, Value::Bytes(dense)) => block.dense_nodes(dense, &mut self.pending)?,
+                    (3, Value::Bytes(way)) => self.pending.push_back(block.way(way)?),
+                    (4, Value::Bytes(relation)) => {
+                        self.pending.push_back(block.relation(relation)?)
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        for element in self.pending.iter().skip(first) {
+            register_element(statistics, element);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Block<'a> {
+    fn node(&self, data: &[u8]) -> Result<Element, OsmParseError> {
+        let mut node = Node::default();
+        let (mut keys, mut vals) = (Vec::new(), Vec::new());
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Varint(id)) => node.id = zigzag(id),
+                (2, value) => repeated(value, &mut keys)?,
+                (3, value) => repeated(value, &mut vals)?,
+                (4, Value::Bytes(info)) => node.meta = self.info(info)?,
+                (8, Value::Varint(lat)) => node.lat = self.lat(zigzag(lat))?,
+                (9, Value::Varint(lon)) => node.lon = self.lon(zigzag(lon))?,
+                _ => (),
+            }
+        }
+        node.tags = self.tags(&keys, &vals)?;
+
+        Ok(Element::Node(node))
+    }
+
+    fn dense_nodes(&self, data: &[u8], nodes: &mut VecDeque<Element>) -> Result<(), OsmParseError> {
+        let (mut ids, mut lats, mut lons) = (Vec::new(), Vec::new(), Vec::new());
+        let mut keys_vals = Vec::new();
+        let mut info = None;
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, value) => delta_coded(value, &mut ids)?,
+                (5, Value::Bytes(dense_info)) => info = Some(dense_info),
+                (8, value) => delta_coded(value, &mut lats)?,
+                (9, value) => delta_coded(value, &mut lons)?,
+                (10, value) => repeated(value, &mut keys_vals)?,
+                _ => (),
+            }
+        }
+
+        if lats.len() != ids.len() || lons.len() != ids.len() {
+            return Err(OsmParseError::pbf("dense nodes with mismatched arrays"));
+        }
+        let mut metas = match info {
+            Some(info) => self.dense_info(info, ids.len())?.into_iter(),
+            None => Vec::new().into_iter(),
+        };
+
+        let mut keys_vals = keys_vals.into_iter();
+        for ((id, lat), lon) in ids.into_iter().zip(lats).zip(lons) {
+            let mut tags = Tags::new();
+            loop {
+                match keys_vals.next() {
+                    None | Some(0) => break,
+                    Some(key) => {
+                        let value = keys_vals
+                            .next()
+                            .ok_or_else(|| OsmParseError::pbf("dense node key without value"))?;
+                        tags.push((self.string(key)?, self.string(value)?));
+                    }
+                }
+            }
+
+            nodes.push_back(Element::Node(Node {
+                id,
+                lat: self.lat(lat)?,
+                lon: self.lon(lon)?,
+                meta: metas.next().unwrap_or_default(),
+                tags,
+            }));
+        }
+
+        Ok(())
+    }
+
+    fn dense_info(&self, data: &[u8], count: usize) -> Result<Vec<Meta>, OsmParseError> {
+        let mut versions = Vec::new();
+        let (mut timestamps, mut changesets) = (Vec::new(), Vec::new());
+        let (mut uids, mut user_sids) = (Vec::new(), Vec::new());
+        let mut visibles = Vec::new();
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, value) => repeated(value, &mut versions)?,
+                (2, value) => delta_coded(value, &mut timestamps)?,
+                (3, value) => delta_coded(value, &mut changesets)?,
+                (4, value) => delta_coded(value, &mut uids)?,
+                (5, value) => delta_coded(value, &mut user_sids)?,
+                (6, value) => repeated(value, &mut visibles)?,
+                _ => (),
+            }
+        }
+
+        (0..count)
+            .map(|i| {
+                Ok(Meta {
+                    version: versions.get(i).map(|&v| v as u32),
+                    timestamp: timestamps.get(i).map(|&t| self.timestamp(t)).transpose()?,
+                    changeset: changesets.get(i).copied(),
+                    user: match user_sids.get(i) {
+                        Some(&sid) if sid > 0 => Some(self.string(sid as u64)?),
+                        _ => None,
+                    },
+                    uid: uids.get(i).copied(),
+                    visible: visibles.get(i).is_none_or(|&v| v != 0),
+                })
+            })
+            .collect()
+    }
+
+    fn way(&self, data: &[u8]) -> Result<Element, OsmParseError> {
+        let mut way = Way::default();
+        let (mut keys, mut vals) = (Vec::new(), Vec::new());
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Varint(id)) => way.id = id as i64,
+                (2, value) => repeated(value, &mut keys)?,
+                (3, value) => repeated(value, &mut vals)?,
+                (4, Value::Bytes(info)) => way.meta = self.info(info)?,
+                (8, value) => delta_coded(value, &mut way.refs)?,
+                _ => (),
+            }
+        }
+        way.tags = self.tags(&keys, &vals)?;
+
+        Ok(Element::Way(way))
+    }
+
+    fn relation(&self, data: &[u8]) -> Result<Element, OsmParseError> {
+        let mut relation = Relation::default();
+        let (mut keys, mut vals) = (Vec::new(), Vec::new());
+        let (mut roles, mut ids, mut types) = (Vec::new(), Vec::new(), Vec::new());
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Varint(id)) => relation.id = id as i64,
+                (2, value) => repeated(value, &mut keys)?,
+                (3, value) => repeated(value, &mut vals)?,
+                (4, Value::Bytes(info)) => relation.meta = self.info(info)?,
+                (8, value) => repeated(value, &mut roles)?,
+                (9, value) => delta_coded(value, &mut ids)?,
+                (10, value) => repeated(value, &mut types)?,
+                _ => (),
+            }
+        }
+        relation.tags = self.tags(&keys, &vals)?;
+
+        if roles.len() != ids.len() || types.len() != ids.len() {
+            return Err(OsmParseError::pbf("relation with mismatched member arrays"));
+        }
+        for ((role, member_ref), member_type) in roles.into_iter().zip(ids).zip(types) {
+            let member_type = match member_type {
+                0 => OsmTag::Node,
+                1 => OsmTag::Way,
+                2 => OsmTag::Relation,
+                other => return Err(OsmParseError::pbf(format!("unknown member type {}", other))),
+            };
+
+            relation.members.push(Member {
+                member_type,
+                member_ref,
+                role: self.string(role)?,
+            });
+        }
+
+        Ok(Element::Relation(relation))
+    }
+
+    fn info(&self, data: &[u8]) -> Result<Meta, OsmParseError> {
+        let mut meta = Meta::default();
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Value::Varint(version)) => meta.version = u32::try_from(version).ok(),
+                (2, Value::Varint(timestamp)) => {
+                    meta.timestamp = Some(self.timestamp(timestamp as i64)?)
+                }
+                (3, Value::Varint(changeset)) => meta.changeset = Some(changeset as i64),
+                (4, Value::Varint(uid)) => meta.uid = Some(uid as i32 as i64),
+                (5, Value::Varint(sid)) if sid > 0 => meta.user = Some(self.string(sid)?),
+                (6, Value::Varint(visible)) => meta.visible = visible != 0,
+                _ => (),
+            }
+        }
+
+        Ok(meta)
+    }
+
+    fn tags(&self, keys: &[u64], vals: &[u64]) -> Result<Tags, OsmParseError> {
+        if keys.len() != vals.len() {
+            return Err(OsmParseError::pbf("mismatched tag keys and values"));
+        }
+
+        keys.iter()
+            .zip(vals)
+            .map(|(&key, &value)| Ok((self.string(key)?, self.string(value)?)))
+            .collect()
+    }
+
+    fn string(&self, index: u64) -> Result<String, OsmParseError> {
+        self.strings
+            .get(index as usize)
+            .map(|s| s.to_string())
+            .ok_or_else(|| OsmParseError::pbf(format!("string index {} out of range", index)))
+    }
+
+    fn lat(&self, lat: i64) -> Result<i64, OsmParseError> {
+        self.coordinate(self.lat_offset, lat)
+    }
+
+    fn lon(&self, lon: i64) -> Result<i64, OsmParseError> {
+        self.coordinate(self.lon_offset, lon)
+    }
+
+    /// Convert a coordinate in `granularity` units to nanodegrees
+    fn coordinate(&self, offset: i64, value: i64) -> Result<i64, OsmParseError> {
+        self.granularity
+            .checked_mul(value)
+            .and_then(|value| value.checked_add(offset))
+            .ok_or_else(|| OsmParseError::pbf("coordinate out of range"))
+    }
+
+    /// Convert a timestamp in `date_granularity` units to seconds
+    fn timestamp(&self, timestamp: i64) -> Result<i64, OsmParseError> {
+        timestamp
+            .checked_mul(self.date_granularity)
+            .map(|milliseconds| milliseconds / 1000)
+            .ok_or_else(|| OsmParseError::pbf("timestamp out of range"))
+    }
+}
+
+/// Uncompress the content of a `Blob` message
+fn decode_blob(blob: &[u8]) -> Result<Vec<u8>, OsmParseError> {
+    let mut raw_size = 0;
+    let mut zlib_data = None;
+
+    for field in Fields::new(blob) {
+        match field? {
+            (1, Value::Bytes(raw)) => return Ok(raw.to_vec()),
+            (2, Value::Varint(size)) => raw_size = size as usize,
+            (3, Value::Bytes(data)) => zlib_data = Some(data),
+            (4..=7, _) => {
+                return Err(OsmParseError::pbf(
+                    "only raw and zlib compressed blobs are supported",
+                ))
+            }
+            _ => (),
+        }
+    }
+
+    let zlib_data = zlib_data.ok_or_else(|| OsmParseError::pbf("blob without data"))?;
+    let mut data = Vec::with_capacity(raw_size.min(MAX_BLOB_SIZE as usize));
+    ZlibDecoder::new(zlib_data)
+        .read_to_end(&mut data)
+        .map_err(|source| OsmParseError::Decompression {
+            source,
+            position: Position::default(),
+        })?;
+
+    Ok(data)
+}
+
+/// Count an element and its children as the XML tokeniser would
+fn register_element(statistics: &mut Statistics, element: &Element) {
+    let name: &[u8] = match element.tag() {
+        OsmTag::Node => b"node",
+        OsmTag::Way => b"way",
+        OsmTag::Relation => b"relation",
+    };
+    let children: usize = match element {
+        Element::Node(_) => 0,
+        Element::Way(way) => way.refs.len(),
+        Element::Relation(relation) => relation.members.len(),
+    };
+    let child: &[u8] = match element {
+        Element::Way(_) => b"nd",
+        _ => b"member",
+    };
+
+    statistics.register_tag(true, name, Some(b"osm"));
+    for _ in 0..children {
+        statistics.register_tag(true, child, Some(name));
+        statistics.register_tag(false, child, None);
+    }
+    for _ in element.tags() {
+        statistics.register_tag(true, b"tag", Some(name));
+        statistics.register_tag(false, b"tag", None);
+    }
+    statistics.register_tag(false, name, None);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn block(strings: &[&'static str]) -> Block<'static> {
+        Block {
+            strings: strings.iter().map(|&s| Cow::Borrowed(s)).collect(),
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+            date_granularity: 1000,
+        }
+    }
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    /// A length delimited field
+    fn bytes_field(field: u32, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(u64::from(field) << 3 | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    /// A packed repeated field
+    fn packed(field: u32, values: &[u64], out: &mut Vec<u8>) {
+        let mut packed = Vec::new();
+        for &value in values {
+            varint(value, &mut packed);
+        }
+        bytes_field(field, &packed, out);
+    }
+
+    /// A packed `sint64` field, delta coded
+    fn delta_packed(field: u32, values: &[i64], out: &mut Vec<u8>) {
+        let mut last = 0;
+        let deltas: Vec<_> = values
+            .iter()
+            .map(|&value| {
+                let delta = value - last;
+                last = value;
+                ((delta << 1) ^ (delta >> 63)) as u64
+            })
+            .collect();
+        packed(field, &deltas, out);
+    }
+
+    fn dense(keys_vals: &[u64]) -> Vec<u8> {
+        let mut dense = Vec::new();
+        delta_packed(1, &[10, 11, 15], &mut dense);
+        delta_packed(8, &[515_000_000, 515_000_100, -1], &mut dense);
+        delta_packed(9, &[-1_000, 0, 1_800_000_000], &mut dense);
+        packed(10, keys_vals, &mut dense);
+
+        dense
+    }
+
+    fn tags(element: &Element) -> Vec<(&str, &str)> {
+        element
+            .tags()
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn dense_nodes() {
+        let block = block(&["", "amenity", "cafe", "name", "Chez Paul"]);
+        let mut nodes = VecDeque::new();
+        block
+            .dense_nodes(&dense(&[1, 2, 3, 4, 0, 0, 3, 2, 0]), &mut nodes)
+            .unwrap();
+
+        let nodes: Vec<_> = nodes.into_iter().collect();
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(
+            tags(&nodes[0]),
+            [("amenity", "cafe"), ("name", "Chez Paul")]
+        );
+        assert_eq!(tags(&nodes[1]), []);
+        assert_eq!(tags(&nodes[2]), [("name", "cafe")]);
+
+        match &nodes[..] {
+            [Element::Node(first), Element::Node(second), Element::Node(third)] => {
+                assert_eq!((first.id, second.id, third.id), (10, 11, 15));
+                assert_eq!((first.lat, first.lon), (51_500_000_000, -100_000));
+                assert_eq!((second.lat, second.lon), (51_500_010_000, 0));
+                assert_eq!((third.lat, third.lon), (-100, 180_000_000_000));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn dense_nodes_without_tags() {
+        let block = block(&[""]);
+        let mut nodes = VecDeque::new();
+        block.dense_nodes(&dense(&[]), &mut nodes).unwrap();
+
+        assert_eq!(nodes.len(), 3);
+        assert!(nodes.iter().all(|node| node.tags().is_empty()));
+    }
+
+    #[test]
+    fn dense_nodes_errors() {
+        let block = block(&["", "amenity"]);
+        let mut nodes = VecDeque::new();
+        // A key without a value, and a string index out of range
+        assert!(block.dense_nodes(&dense(&[1]), &mut nodes).is_err());
+        assert!(block.dense_nodes(&dense(&[1, 7, 0]), &mut nodes).is_err());
+
+        let mut mismatched = Vec::new();
+        delta_packed(1, &[1, 2], &mut mismatched);
+        delta_packed(8, &[0], &mut mismatched);
+        delta_packed(9, &[0, 0], &mut mismatched);
+        assert!(block.dense_nodes(&mismatched, &mut nodes).is_err());
+    }
+
+    #[test]
+    fn coordinate_scaling() {
+        let mut block = block(&[]);
+        assert_eq!(block.lat(515_000_000).unwrap(), 51_500_000_000);
+
+        block.granularity = 1_000;
+        block.lat_offset = 5;
+        block.lon_offset = -7;
+        assert_eq!(block.lat(-3).unwrap(), -2_995);
+        assert_eq!(block.lon(2).unwrap(), 1_993);
+
+        assert!(block.lat(i64::MAX / 100).is_err());
+        block.granularity = 1;
+        block.lon_offset = i64::MIN;
+        assert!(block.lon(-1).is_err());
+    }
+
+    #[test]
+    fn timestamp_scaling() {
+        let mut block = block(&[]);
+        assert_eq!(block.timestamp(1_600_000_000).unwrap(), 1_600_000_000);
+
+        block.date_granularity = 1;
+        assert_eq!(block.timestamp(1_600_000_000_500).unwrap(), 1_600_000_000);
+        block.date_granularity = 60_000;
+        assert_eq!(block.timestamp(2).unwrap(), 120);
+
+        assert!(block.timestamp(i64::MAX / 1_000).is_err());
+        assert!(matches!(
+            block.timestamp(i64::MIN),
+            Err(OsmParseError::Pbf { .. })
+        ));
+    }
+
+    #[test]
+    fn blobs() {
+        let mut raw = Vec::new();
+        bytes_field(1, b"data", &mut raw);
+        assert_eq!(decode_blob(&raw).unwrap(), b"data");
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"compressed data").unwrap();
+        let mut zlib = Vec::new();
+        bytes_field(3, &encoder.finish().unwrap(), &mut zlib);
+        assert_eq!(decode_blob(&zlib).unwrap(), b"compressed data");
+
+        let mut corrupt = Vec::new();
+        bytes_field(3, b"not zlib", &mut corrupt);
+        assert!(matches!(
+            decode_blob(&corrupt),
+            Err(OsmParseError::Decompression { .. })
+        ));
+    }
+}
@@ -0,0 +1,185 @@
+//! Live progress line on standard error
+
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use osm_parse::OsmTag;
+
+/// Time between updates of the progress line
+const INTERVAL: Duration = Duration::from_millis(250);
+
+const BYTES_PER_MB: f64 = 1_000_000.0;
+
+/// Number of elements parsed per OSM tag, shared with the progress line
+#[derive(Default)]
+pub struct Counters {
+    nodes: AtomicU64,
+    ways: AtomicU64,
+    relations: AtomicU64,
+}
+
+impl Counters {
+    pub fn count(&self, tag: OsmTag) {
+        let counter = match tag {
+            OsmTag::Node => &self.nodes,
+            OsmTag::Way => &self.ways,
+            OsmTag::Relation => &self.relations,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn total(&self) -> u64 {
+        [&self.nodes, &self.ways, &self.relations]
+            .iter()
+            .map(|counter| counter.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+/// Input read and elements parsed at some time since the start
+#[derive(Debug, Default, Clone, Copy)]
+struct Sample {
+    elapsed: Duration,
+    position: u64,
+    elements: u64,
+}
+
+/// Redraws the progress line until dropped, which clears it again
+pub struct ProgressLine {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProgressLine {
+    /// Start showing the progress of reading `size` bytes of input, or
+    /// `None` when standard error is not a terminal
+    pub fn start(
+        bytes_read: Arc<AtomicU64>,
+        size: Option<u64>,
+        counters: Arc<Counters>,
+    ) -> Option<Self> {
+        if !io::stderr().is_terminal() {
+            return None;
+        }
+
+        let (stop, stopped) = channel();
+        let started = Instant::now();
+        let thread = thread::spawn(move || {
+            // Rates are measured since the previous redraw, so they follow
+            // the current speed rather than the average since the start
+            let mut previous = Sample::default();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                let sample = Sample {
+                    elapsed: started.elapsed(),
+                    position: bytes_read.load(Ordering::Relaxed),
+                    elements: counters.total(),
+                };
+                let line = progress_line(sample, previous, size, &counters);
+                let _ = write!(io::stderr(), "\r{}\x1b[K", line);
+                previous = sample;
+            }
+        });
+
+        Some(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ProgressLine {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = write!(io::stderr(), "\r\x1b[K");
+    }
+}
+
+fn progress_line(
+    sample: Sample,
+    previous: Sample,
+    size: Option<u64>,
+    counters: &Counters,
+) -> String {
+    let position = sample.position;
+    let seconds = sample
+        .elapsed
+        .saturating_sub(previous.elapsed)
+        .as_secs_f64()
+        .max(f64::EPSILON);
+    let mut line = match size {
+        Some(size) if size > 0 => format!(
+            "{:.1}/{:.1} MB ({:.0}%)",
+            position as f64 / BYTES_PER_MB,
+            size as f64 / BYTES_PER_MB,
+            position as f64 * 100.0 / size as f64
+        ),
+        _ => format!("{:.1} MB", position as f64 / BYTES_PER_MB),
+    };
+
+    line += &format!(
+        " | nodes {} ways {} relations {} | {:.0} elements/s",
+        counters.nodes.load(Ordering::Relaxed),
+        counters.ways.load(Ordering::Relaxed),
+        counters.relations.load(Ordering::Relaxed),
+        sample.elements.saturating_sub(previous.elements) as f64 / seconds
+    );
+
+    // The recent input rate predicts the remaining time
+    let read = position.saturating_sub(previous.position);
+    if let Some(size) = size.filter(|_| read > 0) {
+        let remaining = size.saturating_sub(position) as f64 * seconds / read as f64;
+        let remaining = remaining.round() as u64;
+        line += &format!(" | ETA {}:{:02}", remaining / 60, remaining % 60);
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(millis: u64, position: u64, elements: u64) -> Sample {
+        Sample {
+            elapsed: Duration::from_millis(millis),
+            position,
+            elements,
+        }
+    }
+
+    #[test]
+    fn rates_over_recent_interval() {
+        let counters = Counters::default();
+
+        // A slow start does not hold down the current rate
+        let previous = sample(10_000, 1_000_000, 1_000);
+        let line = progress_line(
+            sample(10_500, 2_000_000, 51_000),
+            previous,
+            Some(62_000_000),
+            &counters,
+        );
+        assert!(line.ends_with("| 100000 elements/s | ETA 0:30"), "{}", line);
+
+        // Without progress in the interval, no time is predicted
+        let previous = sample(10_500, 2_000_000, 51_000);
+        let line = progress_line(
+            sample(10_750, 2_000_000, 51_000),
+            previous,
+            Some(62_000_000),
+            &counters,
+        );
+        assert!(line.ends_with("| 0 elements/s"), "{}", line);
+    }
+}
@@ -0,0 +1,235 @@
+//! Just enough protocol buffer wire format decoding for the OSM PBF messages
+
+use crate::OsmParseError;
+
+/// A single field value, as found on the wire
+pub(crate) enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterator over the `(field number, value)` pairs of an encoded message
+pub(crate) struct Fields<'a> {
+    data: &'a [u8],
+}
+
+/// Iterator over the varints of a packed repeated field
+pub(crate) struct Packed<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Value<'a>), OsmParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        Some(self.next_field())
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn next_field(&mut self) -> Result<(u32, Value<'a>), OsmParseError> {
+        let key = varint(&mut self.data)?;
+        let field = (key >> 3) as u32;
+
+        let value = match key & 7 {
+            0 => Value::Varint(varint(&mut self.data)?),
+            1 => {
+                self.skip(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let length = varint(&mut self.data)? as usize;
+                let bytes = self
+                    .data
+                    .get(..length)
+                    .ok_or_else(|| truncated("length delimited field"))?;
+                self.data = &self.data[length..];
+                Value::Bytes(bytes)
+            }
+            5 => {
+                self.skip(4)?;
+                Value::Fixed
+            }
+            wire_type => {
+                return Err(OsmParseError::pbf(format!(
+                    "unsupported wire type {} for field {}",
+                    wire_type, field
+                )))
+            }
+        };
+
+        Ok((field, value))
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), OsmParseError> {
+        if self.data.len() < count {
+            return Err(truncated("fixed width field"));
+        }
+        self.data = &self.data[count..];
+
+        Ok(())
+    }
+}
+
+impl<'a> Packed<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Packed<'a> {
+    type Item = Result<u64, OsmParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        Some(varint(&mut self.data))
+    }
+}
+
+/// Append the values of a repeated field, which may be packed or not
+pub(crate) fn repeated(value: Value, values: &mut Vec<u64>) -> Result<(), OsmParseError> {
+    match value {
+        Value::Varint(v) => values.push(v),
+        Value::Bytes(data) => {
+            for v in Packed::new(data) {
+                values.push(v?);
+            }
+        }
+        Value::Fixed => return Err(OsmParseError::pbf("unexpected fixed width value")),
+    }
+
+    Ok(())
+}
+
+/// Decode the delta coded, zigzag encoded values of a repeated `sint` field
+pub(crate) fn delta_coded(value: Value, values: &mut Vec<i64>) -> Result<(), OsmParseError> {
+    let mut raw = Vec::new();
+    repeated(value, &mut raw)?;
+
+    let mut last = values.last().copied().unwrap_or_default();
+    for v in raw {
+        last = last.wrapping_add(zigzag(v));
+        values.push(last);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn varint(data: &mut &[u8]) -> Result<u64, OsmParseError> {
+    let mut value = 0u64;
+
+    for (index, byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            *data = &data[index + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(truncated("varint"))
+}
+
+fn truncated(what: &str) -> OsmParseError {
+    OsmParseError::pbf(format!("truncated {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints() {
+        let cases: [(&[u8], u64); 5] = [
+            (&[0x00], 0),
+            (&[0x7f], 127),
+            (&[0x80, 0x01], 128),
+            (&[0xac, 0x02], 300),
+            (
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+                u64::MAX,
+            ),
+        ];
+        for (bytes, expected) in cases {
+            let data = [bytes, &[0x2a]].concat();
+            let mut rest = &data[..];
+            assert_eq!(varint(&mut rest).unwrap(), expected);
+            assert_eq!(rest, [0x2a]);
+        }
+
+        assert!(varint(&mut &[0x80, 0x80][..]).is_err());
+        assert!(varint(&mut &[][..]).is_err());
+        // No more than ten bytes
+        assert!(varint(&mut &[0x80; 11][..]).is_err());
+    }
+
+    #[test]
+    fn zigzags() {
+        let cases = [
+            (0, 0),
+            (1, -1),
+            (2, 1),
+            (3, -2),
+            (4_294_967_294, 2_147_483_647),
+            (u64::MAX - 1, i64::MAX),
+            (u64::MAX, i64::MIN),
+        ];
+        for (encoded, decoded) in cases {
+            assert_eq!(zigzag(encoded), decoded, "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn delta_coding() {
+        let mut values = Vec::new();
+        // Packed 10, +2, -5, as zigzag 20, 4, 9
+        delta_coded(Value::Bytes(&[20, 4, 9]), &mut values).unwrap();
+        assert_eq!(values, [10, 12, 7]);
+
+        // Unpacked values continue from the last one
+        delta_coded(Value::Varint(3), &mut values).unwrap();
+        assert_eq!(values, [10, 12, 7, 5]);
+
+        assert!(delta_coded(Value::Fixed, &mut values).is_err());
+        assert!(delta_coded(Value::Bytes(&[0x80]), &mut values).is_err());
+    }
+
+    #[test]
+    fn fields() {
+        // Field 1 varint 150, field 2 bytes "ab", field 3 fixed64, field 4
+        // fixed32
+        let data = [
+            [0x08, 0x96, 0x01].as_slice(),
+            &[0x12, 0x02, b'a', b'b'],
+            &[0x19, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0x25, 0, 0, 0, 0],
+        ]
+        .concat();
+        let fields: Vec<_> = Fields::new(&data).map(Result::unwrap).collect();
+        assert!(matches!(fields[0], (1, Value::Varint(150))));
+        assert!(matches!(fields[1], (2, Value::Bytes(b"ab"))));
+        assert!(matches!(fields[2], (3, Value::Fixed)));
+        assert!(matches!(fields[3], (4, Value::Fixed)));
+        assert_eq!(fields.len(), 4);
+
+        let truncated = [0x12, 0x05, b'a'];
+        assert!(Fields::new(&truncated).next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::BufRead;
+
+use crate::{
+    pbf::PbfParser,
+    xml::{BufferedSource, SliceSource, XmlParser},
+    Change, Element, OsmParseError, Position, Statistics,
+};
+
+/// Streaming reader for OSM data
+///
+/// Elements are produced through [`OsmReader::elements`], while every
+/// XML element that passes the tokeniser is counted in [`Statistics`].
+/// PBF input is counted as if it had been written out as XML.
+pub struct OsmReader<R: BufRead> {
+    parser: Parser<R>,
+    statistics: Statistics,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Parser<R: BufRead> {
+    Xml(XmlParser<BufferedSource<R>>),
+    Pbf(PbfParser<R>),
+}
+
+/// Iterator over the top-level elements of an [`OsmReader`]
+pub struct Elements<'r, R: BufRead> {
+    reader: &'r mut OsmReader<R>,
+}
+
+/// Iterator over the top-level elements of an [`OsmReader`], along with
+/// the OsmChange action each is in
+pub struct ChangeElements<'r, R: BufRead> {
+    reader: &'r mut OsmReader<R>,
+}
+
+/// Reader for OSM XML that is completely in memory, such as a memory
+/// mapped file
+///
+/// Unlike an [`OsmReader`], it tokenises the data in place, without
+/// copying it into a buffer first.
+pub struct OsmSliceReader<'a> {
+    parser: XmlParser<SliceSource<'a>>,
+    statistics: Statistics,
+}
+
+/// Iterator over the top-level elements of an [`OsmSliceReader`]
+pub struct SliceElements<'r, 'a> {
+    reader: &'r mut OsmSliceReader<'a>,
+}
+
+impl<R: BufRead> OsmReader<R> {
+    /// Read OSM XML from `input`
+    pub fn new(input: R) -> Self {
+        Self::with_parser(Parser::Xml(XmlParser::new(input)))
+    }
+
+    /// Read OSM PBF from `input`
+    pub fn pbf(input: R) -> Self {
+        Self::with_parser(Parser::Pbf(PbfParser::new(input)))
+    }
+
+    fn with_parser(parser: Parser<R>) -> Self {
+        Self {
+            parser,
+            statistics: Statistics::default(),
+        }
+    }
+
+    pub fn elements(&mut self) -> Elements<'_, R> {
+        Elements { reader: self }
+    }
+
+    /// Elements of an OsmChange document, with their action; PBF input has
+    /// no actions
+    pub fn changes(&mut self) -> ChangeElements<'_, R> {
+        ChangeElements { reader: self }
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    pub fn into_statistics(self) -> Statistics {
+        self.statistics
+    }
+
+    fn next_change(&mut self) -> Result<Option<Change>, OsmParseError> {
+        match &mut self.parser {
+            Parser::Xml(parser) => parser.next_element(&mut self.statistics),
+            Parser::Pbf(parser) => {
+                Ok(parser
+                    .next_element(&mut self.statistics)?
+                    .map(|element| Change {
+                        action: None,
+                        element,
+                    }))
+            }
+        }
+    }
+}
+
+impl<'r, R: BufRead> Iterator for Elements<'r, R> {
+    type Item = Result<Element, OsmParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let change = self.reader.next_change().transpose()?;
+        Some(change.map(|change| change.element))
+    }
+}
+
+impl<'r, R: BufRead> Iterator for ChangeElements<'r, R> {
+    type Item = Result<Change, OsmParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next_change().transpose()
+    }
+}
+
+impl<'a> OsmSliceReader<'a> {
+    /// Read OSM XML from `data`
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            parser: XmlParser::from_slice(data),
+            statistics: Statistics::default(),
+        }
+    }
+
+    pub fn elements(&mut self) -> SliceElements<'_, 'a> {
+        SliceElements { reader: self }
+    }
+
+    /// Position just after the last element read
+    pub fn position(&self) -> Position {
+        self.parser.position()
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    pub fn into_statistics(self) -> Statistics {
+        self.statistics
+    }
+}
+
+impl Iterator for SliceElements<'_, '_> {
+    type Item = Result<Element, OsmParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = &mut *self.reader;
+        let change = reader
+            .parser
+            .next_element(&mut reader.statistics)
+            .transpose()?;
+        Some(change.map(|change| change.element))
+    }
+}
@@ -0,0 +1,358 @@
+//! Reports printed by the command line tool
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use osm_parse::{Action, FileFormats, History, KeyInfo, OsmTag, Statistics, TagStatistics};
+use serde_json::{json, Value};
+
+/// Version of the JSON report layout; raised on incompatible changes
+pub const SCHEMA_VERSION: u32 = 3;
+
+const BYTES_PER_MB: f64 = 1_000_000.0;
+
+/// Layout of the report on standard output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+/// Everything known about a single parse run
+pub struct Report {
+    /// Input file, or `None` for standard input or a total
+    pub path: Option<PathBuf>,
+    /// Input format, or `None` for a total over several files
+    pub format: Option<FileFormats>,
+    pub statistics: Statistics,
+    /// Object versions, when counted
+    pub history: Option<History>,
+    /// Tag key and value frequencies, when counted
+    pub tag_statistics: Option<TagStatistics>,
+    /// Whether the input was memory mapped rather than read through a
+    /// buffer
+    pub mmap: bool,
+    /// Bytes read from the input, as stored
+    pub bytes_read: u64,
+    /// Bytes handed to the parser, after decompression
+    pub bytes_processed: u64,
+    /// Wall-clock time of the complete run
+    pub elapsed: Duration,
+    /// Part of `elapsed` spent waiting on input and its decompression; the
+    /// rest went into tokenising and parsing, which for PBF includes the
+    /// decompression of its blobs
+    pub decompression: Duration,
+}
+
+impl Report {
+    /// Sum of the reports on several files, which took `elapsed` together
+    ///
+    /// When the files were parsed concurrently, their decompression times
+    /// add up to more than the wall-clock time. Structure issues are only
+    /// reported per file.
+    pub fn total(reports: Vec<Report>, elapsed: Duration) -> Self {
+        let mut total = Report {
+            path: None,
+            format: None,
+            statistics: Statistics::default(),
+            history: None,
+            tag_statistics: None,
+            mmap: false,
+            bytes_read: 0,
+            bytes_processed: 0,
+            elapsed,
+            decompression: Duration::ZERO,
+        };
+
+        for report in reports {
+            total.statistics.merge(report.statistics);
+            if let Some(history) = report.history {
+                total
+                    .history
+                    .get_or_insert_with(History::default)
+                    .merge(history);
+            }
+            if let Some(tag_statistics) = report.tag_statistics {
+                match &mut total.tag_statistics {
+                    Some(total) => total.merge(tag_statistics),
+                    None => total.tag_statistics = Some(tag_statistics),
+                }
+            }
+            total.bytes_read += report.bytes_read;
+            total.bytes_processed += report.bytes_processed;
+            total.decompression += report.decompression;
+        }
+        total.statistics.issues.clear();
+
+        total
+    }
+
+    pub fn print(&self, output: OutputFormat) {
+        match output {
+            OutputFormat::Text => {
+                println!(
+                    "... and done! \n\tinfo: {:?}\n\tOthers: {:?}",
+                    self.statistics.info, self.statistics.others
+                );
+                if !self.statistics.changes.is_empty() {
+                    println!("\tchanges: {:?}", self.statistics.changes);
+                }
+                if let Some(history) = &self.history {
+                    println!("\thistory: {:?}", history.info);
+                }
+                if let Some(tag_statistics) = &self.tag_statistics {
+                    print_tag_statistics(tag_statistics);
+                }
+                println!(
+                    "\ttime: {:.3} s (decompression {:.3} s, tokenising {:.3} s)",
+                    self.elapsed.as_secs_f64(),
+                    self.decompression.as_secs_f64(),
+                    self.tokenising().as_secs_f64()
+                );
+                println!(
+                    "\tbytes: {} read, {} processed",
+                    self.bytes_read, self.bytes_processed
+                );
+                println!(
+                    "\tthroughput: {:.1} MB/s compressed, {:.1} MB/s decompressed, {:.0} elements/s",
+                    self.per_second(self.bytes_read as f64 / BYTES_PER_MB),
+                    self.per_second(self.bytes_processed as f64 / BYTES_PER_MB),
+                    self.per_second(self.elements() as f64)
+                );
+            }
+            OutputFormat::Json => println!("{:#}", self.to_json()),
+        }
+    }
+
+    /// The report as JSON; all maps have sorted keys, and every OSM
+    /// element and OsmChange action is present even when it was not seen
+    pub fn to_json(&self) -> Value {
+        let elements: BTreeMap<_, _> = [OsmTag::Node, OsmTag::Way, OsmTag::Relation]
+            .iter()
+            .map(|tag| {
+                let (starts, ends) = self
+                    .statistics
+                    .info
+                    .get(tag)
+                    .map_or((0, 0), |info| (info.starts, info.ends));
+
+                (
+                    format!("{:?}", tag).to_lowercase(),
+                    json!({ "starts": starts, "ends": ends }),
+                )
+            })
+            .collect();
+        let changes: BTreeMap<_, _> = [Action::Create, Action::Modify, Action::Delete]
+            .iter()
+            .map(|action| {
+                let counts: BTreeMap<_, _> = [OsmTag::Node, OsmTag::Way, OsmTag::Relation]
+                    .iter()
+                    .map(|tag| {
+                        let count = self
+                            .statistics
+                            .changes
+                            .get(action)
+                            .and_then(|counts| counts.get(tag))
+                            .copied()
+                            .unwrap_or(0);
+
+                        (format!("{:?}", tag).to_lowercase(), count)
+                    })
+                    .collect();
+
+                (format!("{:?}", action).to_lowercase(), counts)
+            })
+            .collect();
+        let history = self.history.as_ref().map(|history| {
+            [OsmTag::Node, OsmTag::Way, OsmTag::Relation]
+                .iter()
+                .map(|tag| {
+                    let info = history.info.get(tag).cloned().unwrap_or_default();
+
+                    (
+                        format!("{:?}", tag).to_lowercase(),
+                        json!({
+                            "objects": info.objects,
+                            "versions": info.versions,
+                            "deleted": info.deleted,
+                            "distribution": info.distribution,
+                        }),
+                    )
+                })
+                .collect::<BTreeMap<_, _>>()
+        });
+        let others: BTreeMap<_, _> = self
+            .statistics
+            .others
+            .iter()
+            .map(|(name, other_info)| {
+                let parents: BTreeMap<_, _> = other_info.parents.iter().collect();
+
+                (
+                    name,
+                    json!({
+                        "starts": other_info.starts,
+                        "ends": other_info.ends,
+                        "parents": parents,
+                    }),
+                )
+            })
+            .collect();
+        let issues: Vec<_> = self
+            .statistics
+            .issues
+            .iter()
+            .map(|issue| {
+                let position = issue.position();
+
+                json!({
+                    "offset": position.offset,
+                    "line": position.line,
+                    "message": issue.to_string(),
+                })
+            })
+            .collect();
+
+        json!({
+            "schema_version": SCHEMA_VERSION,
+            "input": self.input_json(),
+            "elements": elements,
+            "others": others,
+            "changes": changes,
+            "history": history,
+            "tags": self.tag_statistics.as_ref().map(tag_statistics_json),
+            "issues": issues,
+            "bytes": {
+                "read": self.bytes_read,
+                "processed": self.bytes_processed,
+            },
+            "timing": {
+                "seconds": self.elapsed.as_secs_f64(),
+                "decompression_seconds": self.decompression.as_secs_f64(),
+                "tokenising_seconds": self.tokenising().as_secs_f64(),
+            },
+            "throughput": {
+                "compressed_mb_per_second": self.per_second(self.bytes_read as f64 / BYTES_PER_MB),
+                "decompressed_mb_per_second":
+                    self.per_second(self.bytes_processed as f64 / BYTES_PER_MB),
+                "elements_per_second": self.per_second(self.elements() as f64),
+            },
+        })
+    }
+
+    /// Number of OSM elements parsed
+    fn elements(&self) -> u64 {
+        self.statistics.info.values().map(|info| info.starts).sum()
+    }
+
+    /// Part of `elapsed` not spent on decompression
+    pub fn tokenising(&self) -> Duration {
+        self.elapsed.saturating_sub(self.decompression)
+    }
+
+    fn per_second(&self, amount: f64) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            seconds if seconds > 0.0 => amount / seconds,
+            _ => 0.0,
+        }
+    }
+
+    pub fn input_json(&self) -> Value {
+        let metadata = self.path.as_ref().and_then(|path| fs::metadata(path).ok());
+        // Pipes and other special files have no meaningful size
+        let size = metadata
+            .as_ref()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len());
+        let modified = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs());
+
+        json!({
+            "path": self.path.as_ref().map(|path| path.display().to_string()),
+            "format": self.format.map(FileFormats::name),
+            "size": size,
+            "modified": modified,
+            "mmap": self.mmap,
+        })
+    }
+}
+
+/// Keys of `tag`, most frequent first
+fn sorted_keys(tag_statistics: &TagStatistics, tag: OsmTag) -> Vec<(&String, &KeyInfo)> {
+    let mut keys: Vec<_> = tag_statistics
+        .keys
+        .get(&tag)
+        .into_iter()
+        .flatten()
+        .collect();
+    keys.sort_unstable_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(b.0)));
+
+    keys
+}
+
+fn print_tag_statistics(tag_statistics: &TagStatistics) {
+    for tag in [OsmTag::Node, OsmTag::Way, OsmTag::Relation] {
+        println!("\ttags of {:?}:", tag);
+        for (key, key_info) in sorted_keys(tag_statistics, tag) {
+            let values: Vec<_> = key_info
+                .values
+                .top(tag_statistics.top())
+                .iter()
+                .map(|(value, count, _)| format!("{} ~{}", value, count))
+                .collect();
+            println!("\t\t{} {}: {}", key, key_info.count, values.join(", "));
+        }
+    }
+}
+
+/// Keys per OSM tag, most frequent first, each with its top values
+fn tag_statistics_json(tag_statistics: &TagStatistics) -> Value {
+    let keys: BTreeMap<_, _> = [OsmTag::Node, OsmTag::Way, OsmTag::Relation]
+        .iter()
+        .map(|&tag| {
+            let keys: Vec<_> = sorted_keys(tag_statistics, tag)
+                .into_iter()
+                .map(|(key, key_info)| {
+                    let values: Vec<_> = key_info
+                        .values
+                        .top(tag_statistics.top())
+                        .into_iter()
+                        .map(|(value, count, overestimate)| {
+                            json!({
+                                "value": value,
+                                "count": count,
+                                "overestimate": overestimate,
+                            })
+                        })
+                        .collect();
+
+                    json!({ "key": key, "count": key_info.count, "values": values })
+                })
+                .collect();
+
+            (format!("{:?}", tag).to_lowercase(), keys)
+        })
+        .collect();
+
+    json!({ "top": tag_statistics.top(), "keys": keys })
+}
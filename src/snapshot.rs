@@ -0,0 +1,67 @@
+//! The `snapshot` subcommand: the objects of a history file at a point in
+//! time
+
+use std::path::PathBuf;
+
+use osm_parse::{parse_timestamp, FileFormats, OsmParseError, OsmWriter, Snapshot};
+use structopt::StructOpt;
+
+use crate::{exit_unrecognised, exit_with_error, output::Output};
+
+/// Write the objects of a full-history file as they were at a timestamp
+///
+///    Of every object, the last version at or before the timestamp is
+///    written; objects that were deleted by then, or created after it,
+///    are left out. The history file must be sorted, as history dumps
+///    are. An existing output file is only replaced once the snapshot is
+///    complete.
+#[derive(StructOpt, Debug)]
+pub struct SnapshotOptions {
+    /// Full-history file (.osh, or a compressed .osh extension)
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// Point in time, as YYYY-MM-DDTHH:MM:SSZ
+    #[structopt(long, parse(try_from_str = parse_at))]
+    at: i64,
+
+    /// Write the snapshot to this file instead of standard output
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn parse_at(value: &str) -> Result<i64, String> {
+    parse_timestamp(value)
+        .ok_or_else(|| format!("'{}' is not a YYYY-MM-DDTHH:MM:SSZ timestamp", value))
+}
+
+/// Write the snapshot and return the exit code
+pub fn run(options: &SnapshotOptions) -> i32 {
+    let mut reader = match FileFormats::open(&options.file) {
+        Ok(Some(reader)) => reader,
+        Ok(None) => exit_unrecognised(),
+        Err(e) => exit_with_error(&options.file, &e.into()),
+    };
+    let (output, writer) = Output::create(options.output.as_deref());
+
+    let write = || -> Result<(), OsmParseError> {
+        let mut writer = OsmWriter::new(writer)?;
+        let mut snapshot = Snapshot::new(options.at);
+        for element in reader.elements() {
+            if let Some(element) = snapshot.push(element?) {
+                writer.write(&element)?;
+            }
+        }
+        if let Some(element) = snapshot.finish() {
+            writer.write(&element)?;
+        }
+        writer.finish()?;
+
+        Ok(())
+    };
+
+    match output.finish(write()) {
+        Ok(()) => 0,
+        Err(e) => exit_with_error(&options.file, &e),
+    }
+}
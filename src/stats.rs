@@ -0,0 +1,131 @@
+use std::{collections::HashMap, convert::TryFrom};
+
+use crate::{Action, OsmTag, StructureIssue};
+
+#[derive(Default, Debug, PartialEq)]
+pub struct TagInfo {
+    pub starts: u64,
+    pub ends: u64,
+}
+
+pub type Info = HashMap<OsmTag, TagInfo>;
+
+/// Counts of an element that is not an OSM element, such as `tag` or `nd`
+#[derive(Default, Debug, PartialEq)]
+pub struct OtherInfo {
+    pub starts: u64,
+    pub ends: u64,
+    /// Number of starts under each parent element; starts of the
+    /// document element are not counted here
+    pub parents: HashMap<String, u64>,
+}
+
+/// Counts of every other element name
+pub type OtherTags = HashMap<String, OtherInfo>;
+
+/// Number of elements per OsmChange action and OSM tag
+pub type Changes = HashMap<Action, HashMap<OsmTag, u64>>;
+
+/// Start and end counts of every XML element seen by the tokeniser
+#[derive(Default, Debug, PartialEq)]
+pub struct Statistics {
+    pub info: Info,
+    pub others: OtherTags,
+    /// Only counted for OsmChange documents
+    pub changes: Changes,
+    /// Nesting problems, in document order
+    pub issues: Vec<StructureIssue>,
+}
+
+impl Statistics {
+    /// Add the counts of `other` to these
+    pub fn merge(&mut self, other: Statistics) {
+        for (tag, tag_info) in other.info {
+            let info_entry = self.info.entry(tag).or_default();
+            info_entry.starts += tag_info.starts;
+            info_entry.ends += tag_info.ends;
+        }
+        for (tag_name, other_info) in other.others {
+            let other_entry = self.others.entry(tag_name).or_default();
+            other_entry.starts += other_info.starts;
+            other_entry.ends += other_info.ends;
+            for (parent, count) in other_info.parents {
+                *other_entry.parents.entry(parent).or_default() += count;
+            }
+        }
+        for (action, counts) in other.changes {
+            let change_entry = self.changes.entry(action).or_default();
+            for (tag, count) in counts {
+                *change_entry.entry(tag).or_default() += count;
+            }
+        }
+        self.issues.extend(other.issues);
+    }
+
+    /// Count `count` more elements `tag` under `action`
+    pub(crate) fn register_change(&mut self, action: Action, tag: OsmTag, count: u64) {
+        *self
+            .changes
+            .entry(action)
+            .or_default()
+            .entry(tag)
+            .or_default() += count;
+    }
+
+    /// Count the start (`add`) or end of element `tag`; for a start,
+    /// `parent` is the element it appeared under
+    pub fn register_tag(&mut self, add: bool, tag: &[u8], parent: Option<&[u8]>) {
+        let osm_tag = OsmTag::try_from(tag);
+        match osm_tag {
+            Ok(tag) => {
+                let info_entry = self.info.entry(tag).or_default();
+                match add {
+                    true => info_entry.starts += 1,
+                    false => info_entry.ends += 1,
+                }
+            }
+            Err(_) => {
+                let other_entry = self.other_entry(tag);
+                match add {
+                    true => other_entry.starts += 1,
+                    false => other_entry.ends += 1,
+                }
+                if let (true, Some(parent)) = (add, parent) {
+                    *parent_entry(other_entry, parent) += 1;
+                }
+            }
+        }
+    }
+
+    /// Count `count` more starts of element `tag` under `parent`
+    pub(crate) fn register_parent(&mut self, tag: &str, parent: &str, count: u64) {
+        if OsmTag::try_from(tag.as_bytes()).is_err() {
+            *parent_entry(self.other_entry(tag.as_bytes()), parent.as_bytes()) += count;
+        }
+    }
+
+    fn other_entry(&mut self, tag: &[u8]) -> &mut OtherInfo {
+        // Look up before inserting, so known names are not copied
+        let tag_name = String::from_utf8_lossy(tag);
+        if !self.others.contains_key(tag_name.as_ref()) {
+            self.others
+                .insert(tag_name.to_string(), OtherInfo::default());
+        }
+
+        self.others
+            .get_mut(tag_name.as_ref())
+            .expect("entry was just inserted")
+    }
+}
+
+fn parent_entry<'a>(other_info: &'a mut OtherInfo, parent: &[u8]) -> &'a mut u64 {
+    let parent_name = String::from_utf8_lossy(parent);
+    if !other_info.parents.contains_key(parent_name.as_ref()) {
+        other_info.parents.insert(parent_name.to_string(), 0);
+    }
+
+    other_info
+        .parents
+        .get_mut(parent_name.as_ref())
+        .expect("entry was just inserted")
+}
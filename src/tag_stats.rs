@@ -0,0 +1,259 @@
+//! Frequencies of tag keys and values
+//!
+//! Keys are counted exactly. Values are counted in a Space-Saving sketch
+//! per key, which keeps a fixed number of candidates: a value that is not
+//! tracked replaces the least frequent one and inherits its count as the
+//! possible overestimate. A value on more than one in `capacity` of the
+//! elements with its key is guaranteed to be tracked, while memory stays
+//! bounded for keys such as `name` with a distinct value almost everywhere.
+
+use std::collections::HashMap;
+
+use crate::{Element, OsmTag};
+
+/// Candidates kept per key, as a multiple of the values reported
+const SKETCH_FACTOR: usize = 4;
+
+/// Approximate counts of the most frequent values of a key
+#[derive(Debug, Clone)]
+pub struct TopValues {
+    capacity: usize,
+    /// Estimated count of each candidate, and by how much it may be
+    /// overestimated
+    counts: HashMap<String, (u64, u64)>,
+}
+
+/// Count of a key, and its most frequent values
+#[derive(Debug, Clone)]
+pub struct KeyInfo {
+    pub count: u64,
+    pub values: TopValues,
+}
+
+/// Key and value frequencies per OSM tag
+#[derive(Debug, Clone)]
+pub struct TagStatistics {
+    /// Number of most frequent values reported per key
+    top: usize,
+    pub keys: HashMap<OsmTag, HashMap<String, KeyInfo>>,
+}
+
+impl TopValues {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str, count: u64, error: u64) {
+        if let Some((estimate, overestimate)) = self.counts.get_mut(value) {
+            *estimate += count;
+            *overestimate += error;
+            return;
+        }
+        if self.counts.len() < self.capacity {
+            self.counts.insert(value.to_string(), (count, error));
+            return;
+        }
+
+        // The least frequent candidate makes way
+        let evicted = self
+            .counts
+            .iter()
+            .min_by_key(|(_, (estimate, _))| *estimate)
+            .map(|(value, &(estimate, _))| (value.clone(), estimate));
+        if let Some((evicted, minimum)) = evicted {
+            self.counts.remove(&evicted);
+            self.counts
+                .insert(value.to_string(), (minimum + count, minimum + error));
+        }
+    }
+
+    /// The `n` values with the highest estimated counts, most frequent
+    /// first, along with their estimate and possible overestimate
+    pub fn top(&self, n: usize) -> Vec<(&str, u64, u64)> {
+        let mut top: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(value, &(estimate, overestimate))| (value.as_str(), estimate, overestimate))
+            .collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        top.truncate(n);
+
+        top
+    }
+}
+
+impl TagStatistics {
+    /// Report the `top` most frequent values of each key
+    pub fn new(top: usize) -> Self {
+        Self {
+            top,
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    /// Count the tags of `element`
+    pub fn count(&mut self, element: &Element) {
+        let capacity = (self.top * SKETCH_FACTOR).max(1);
+        let keys = self.keys.entry(element.tag()).or_default();
+
+        for (key, value) in element.tags() {
+            // Look up before inserting, so known keys are not copied
+            if !keys.contains_key(key.as_str()) {
+                keys.insert(
+                    key.clone(),
+                    KeyInfo {
+                        count: 0,
+                        values: TopValues::new(capacity),
+                    },
+                );
+            }
+            let key_info = keys.get_mut(key.as_str()).expect("entry was just inserted");
+            key_info.count += 1;
+            key_info.values.add(value, 1, 0);
+        }
+    }
+
+    /// Add the counts of `other` to these
+    pub fn merge(&mut self, other: TagStatistics) {
+        for (tag, other_keys) in other.keys {
+            let keys = self.keys.entry(tag).or_default();
+            for (key, other_info) in other_keys {
+                match keys.get_mut(&key) {
+                    Some(key_info) => {
+                        key_info.count += other_info.count;
+                        for (value, (count, error)) in other_info.values.counts {
+                            key_info.values.add(&value, count, error);
+                        }
+                    }
+                    None => {
+                        keys.insert(key, other_info);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OsmReader;
+
+    fn sketch(capacity: usize, values: &[(&str, u64)]) -> TopValues {
+        let mut sketch = TopValues::new(capacity);
+        for &(value, count) in values {
+            sketch.add(value, count, 0);
+        }
+
+        sketch
+    }
+
+    #[test]
+    fn add_counts_tracked_values() {
+        let mut values = sketch(3, &[("a", 1), ("b", 2), ("a", 3)]);
+        values.add("b", 1, 1);
+        assert_eq!(values.top(5), [("a", 4, 0), ("b", 3, 1)]);
+        assert_eq!(values.top(1), [("a", 4, 0)]);
+    }
+
+    #[test]
+    fn add_evicts_least_frequent() {
+        let mut values = sketch(2, &[("a", 3), ("b", 1)]);
+
+        // "c" takes the place of "b", and may have been seen as often
+        values.add("c", 1, 0);
+        assert_eq!(values.top(5), [("a", 3, 0), ("c", 2, 1)]);
+
+        // The overestimate of a merged candidate adds to that of the
+        // evicted one
+        values.add("d", 2, 1);
+        assert_eq!(values.top(5), [("d", 4, 3), ("a", 3, 0)]);
+        assert_eq!(values.counts.len(), 2);
+    }
+
+    fn tag_statistics(top: usize, document: &str) -> TagStatistics {
+        let mut tag_statistics = TagStatistics::new(top);
+        for element in OsmReader::new(document.as_bytes()).elements() {
+            tag_statistics.count(&element.unwrap());
+        }
+
+        tag_statistics
+    }
+
+    /// Tag, key, count and top values of each key
+    type Summary<'a> = Vec<(OsmTag, &'a str, u64, Vec<(&'a str, u64, u64)>)>;
+
+    fn summary(tag_statistics: &TagStatistics) -> Summary<'_> {
+        let mut summary: Vec<_> = tag_statistics
+            .keys
+            .iter()
+            .flat_map(|(&tag, keys)| {
+                keys.iter()
+                    .map(move |(key, info)| (tag, key.as_str(), info.count, info.values.top(10)))
+            })
+            .collect();
+        summary.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+        summary
+    }
+
+    #[test]
+    fn merge() {
+        let left = r#"<osm>
+            <node id="1" lat="0" lon="0"><tag k="amenity" v="cafe"/></node>
+            <node id="2" lat="0" lon="0"><tag k="amenity" v="cafe"/></node>
+            <way id="1"><tag k="highway" v="primary"/></way>
+            </osm>"#;
+        let right = r#"<osm>
+            <node id="3" lat="0" lon="0"><tag k="amenity" v="bench"/></node>
+            <node id="4" lat="0" lon="0"><tag k="amenity" v="cafe"/></node>
+            <relation id="1"><tag k="type" v="route"/></relation>
+            </osm>"#;
+
+        let mut merged = tag_statistics(2, left);
+        merged.merge(tag_statistics(2, right));
+        assert_eq!(
+            summary(&merged),
+            [
+                (
+                    OsmTag::Node,
+                    "amenity",
+                    4,
+                    vec![("cafe", 3, 0), ("bench", 1, 0)]
+                ),
+                (OsmTag::Way, "highway", 1, vec![("primary", 1, 0)]),
+                (OsmTag::Relation, "type", 1, vec![("route", 1, 0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_into_full_sketch() {
+        // With one value reported, four candidates are kept per key
+        let node = |id: usize, value: &str| {
+            format!(r#"<node id="{id}" lat="0" lon="0"><tag k="name" v="{value}"/></node>"#)
+        };
+        let left: String = ["a", "a", "b", "b", "b", "c", "c", "d", "d"]
+            .iter()
+            .enumerate()
+            .map(|(id, value)| node(id, value))
+            .collect();
+        let right = node(100, "e") + &node(101, "e") + &node(102, "e");
+
+        let mut merged = tag_statistics(1, &format!("<osm>{}</osm>", left));
+        merged.merge(tag_statistics(1, &format!("<osm>{}</osm>", right)));
+        let info = &merged.keys[&OsmTag::Node]["name"];
+        assert_eq!(info.count, 12);
+        // "e" evicts a value seen twice, so may have been seen twice more
+        assert_eq!(info.values.top(1), [("e", 5, 2)]);
+        assert_eq!(info.values.counts.len(), 4);
+        assert_eq!(info.values.counts["b"], (3, 0));
+    }
+}
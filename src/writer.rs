@@ -0,0 +1,197 @@
+//! Writing of OSM XML
+
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use quick_xml::escape::escape;
+
+use crate::{
+    attributes::{format_nanodegrees, format_timestamp},
+    Element, Meta, OsmTag, Tags,
+};
+
+/// Writes elements as an OSM XML document
+///
+/// Elements are written in the order they are given; the document is only
+/// complete after [`OsmWriter::finish`].
+pub struct OsmWriter<W: Write> {
+    output: W,
+}
+
+impl<W: Write> OsmWriter<W> {
+    /// Start a document on `output`
+    pub fn new(mut output: W) -> io::Result<Self> {
+        writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            output,
+            r#"<osm version="0.6" generator="osm-parse {}">"#,
+            env!("CARGO_PKG_VERSION")
+        )?;
+
+        Ok(Self { output })
+    }
+
+    pub fn write(&mut self, element: &Element) -> io::Result<()> {
+        let output = &mut self.output;
+        match element {
+            Element::Node(node) => {
+                write!(output, r#"  <node id="{}""#, node.id)?;
+                write_meta(output, &node.meta)?;
+                if node.meta.visible {
+                    write!(
+                        output,
+                        r#" lat="{}" lon="{}""#,
+                        format_nanodegrees(node.lat),
+                        format_nanodegrees(node.lon)
+                    )?;
+                }
+                match node.tags.is_empty() {
+                    true => writeln!(output, "/>"),
+                    false => {
+                        writeln!(output, ">")?;
+                        write_tags(output, &node.tags)?;
+                        writeln!(output, "  </node>")
+                    }
+                }
+            }
+            Element::Way(way) => {
+                write!(output, r#"  <way id="{}""#, way.id)?;
+                write_meta(output, &way.meta)?;
+                if way.refs.is_empty() && way.tags.is_empty() {
+                    return writeln!(output, "/>");
+                }
+                writeln!(output, ">")?;
+                for node_ref in &way.refs {
+                    writeln!(output, r#"    <nd ref="{}"/>"#, node_ref)?;
+                }
+                write_tags(output, &way.tags)?;
+                writeln!(output, "  </way>")
+            }
+            Element::Relation(relation) => {
+                write!(output, r#"  <relation id="{}""#, relation.id)?;
+                write_meta(output, &relation.meta)?;
+                if relation.members.is_empty() && relation.tags.is_empty() {
+                    return writeln!(output, "/>");
+                }
+                writeln!(output, ">")?;
+                for member in &relation.members {
+                    writeln!(
+                        output,
+                        r#"    <member type="{}" ref="{}" role="{}"/>"#,
+                        tag_name(member.member_type),
+                        member.member_ref,
+                        escape_attribute(&member.role)
+                    )?;
+                }
+                write_tags(output, &relation.tags)?;
+                writeln!(output, "  </relation>")
+            }
+        }
+    }
+
+    /// End the document and return the output, flushed
+    pub fn finish(mut self) -> io::Result<W> {
+        writeln!(self.output, "</osm>")?;
+        self.output.flush()?;
+
+        Ok(self.output)
+    }
+}
+
+fn write_meta(output: &mut impl Write, meta: &Meta) -> io::Result<()> {
+    if let Some(version) = meta.version {
+        write!(output, r#" version="{}""#, version)?;
+    }
+    if let Some(timestamp) = meta.timestamp {
+        write!(output, r#" timestamp="{}""#, format_timestamp(timestamp))?;
+    }
+    if let Some(changeset) = meta.changeset {
+        write!(output, r#" changeset="{}""#, changeset)?;
+    }
+    if let Some(uid) = meta.uid {
+        write!(output, r#" uid="{}""#, uid)?;
+    }
+    if let Some(user) = &meta.user {
+        write!(output, r#" user="{}""#, escape_attribute(user))?;
+    }
+    if !meta.visible {
+        write!(output, r#" visible="false""#)?;
+    }
+
+    Ok(())
+}
+
+fn write_tags(output: &mut impl Write, tags: &Tags) -> io::Result<()> {
+    for (key, value) in tags {
+        writeln!(
+            output,
+            r#"    <tag k="{}" v="{}"/>"#,
+            escape_attribute(key),
+            escape_attribute(value)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Escape an attribute value, including the whitespace that XML parsers
+/// would otherwise normalise to spaces
+fn escape_attribute(value: &str) -> Cow<'_, str> {
+    let escaped = escape(value);
+    if !escaped.contains(['\n', '\r', '\t']) {
+        return escaped;
+    }
+
+    Cow::Owned(
+        escaped
+            .replace('\n', "&#10;")
+            .replace('\r', "&#13;")
+            .replace('\t', "&#9;"),
+    )
+}
+
+fn tag_name(tag: OsmTag) -> &'static str {
+    match tag {
+        OsmTag::Node => "node",
+        OsmTag::Way => "way",
+        OsmTag::Relation => "relation",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Member, Node, OsmReader, Relation};
+
+    #[test]
+    fn attribute_whitespace_round_trip() {
+        let text = "a\nb\r\nc\td <&>\"'";
+        let mut node = Node::default();
+        node.meta.user = Some(text.to_string());
+        node.tags.push((text.to_string(), text.to_string()));
+        let relation = Relation {
+            members: vec![Member {
+                member_type: OsmTag::Node,
+                member_ref: 1,
+                role: text.to_string(),
+            }],
+            ..Relation::default()
+        };
+        let elements = [Element::Node(node), Element::Relation(relation)];
+
+        let mut writer = OsmWriter::new(Vec::new()).unwrap();
+        for element in &elements {
+            writer.write(element).unwrap();
+        }
+        let output = writer.finish().unwrap();
+        assert!(output.iter().all(|&b| b != b'\t' && b != b'\r'));
+
+        let read: Vec<_> = OsmReader::new(&output[..])
+            .elements()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, elements);
+    }
+}
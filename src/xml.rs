@@ -0,0 +1,1072 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{self, BufRead, Read},
+};
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::{
+    attributes::{parse_nanodegrees, parse_timestamp, parse_visible},
+    Action, Change, Element, Member, Meta, Node, OsmParseError, OsmTag, Position, Relation,
+    Statistics, StructureIssue, Way,
+};
+
+/// Event driven parser for OSM XML data
+pub(crate) struct XmlParser<S> {
+    source: S,
+    current: Option<Element>,
+    /// Depth of the stack with `current` open
+    current_depth: usize,
+    /// OsmChange block the parser is in, with the depth of the stack with
+    /// it open
+    action: Option<(Action, usize)>,
+    stack: ElementStack,
+}
+
+/// Where an [`XmlParser`] reads its events from
+pub(crate) trait XmlSource {
+    /// Read the next event, along with the position just after it
+    fn read_event(&mut self) -> Result<(Event<'_>, Position), quick_xml::Error>;
+
+    /// Position in the complete document of the last event read
+    fn position(&self) -> Position;
+}
+
+/// Reads events into a buffer of its own
+pub(crate) struct BufferedSource<R> {
+    reader: Reader<LineCounter<R>>,
+    buf: Vec<u8>,
+    /// Position of the input within the complete document
+    start: Position,
+}
+
+/// Reads events borrowing from data in memory, without copying
+pub(crate) struct SliceSource<'a> {
+    reader: Reader<&'a [u8]>,
+    data: &'a [u8],
+    /// Position of the data within the complete document
+    start: Position,
+    /// Lines in `data` before `counted`
+    lines: u64,
+    counted: usize,
+}
+
+/// Names of the currently open elements, innermost last
+#[derive(Default)]
+struct ElementStack {
+    names: Vec<u8>,
+    /// End of each name in `names`
+    ends: Vec<usize>,
+    /// Only set when parsing a fragment, in which end tags of elements
+    /// opened before it are expected
+    fragment: Option<Fragment>,
+}
+
+/// How a fragment relates to the elements opened before it
+#[derive(Default, Debug)]
+pub(crate) struct Fragment {
+    /// End tags closing elements that were opened before the fragment,
+    /// in document order
+    pub(crate) closed: Vec<(String, Position)>,
+    /// Elements started outside any element of the fragment itself: the
+    /// counts at index `i` are those started after `i` of the `closed` tags
+    pub(crate) unresolved: Vec<HashMap<String, u64>>,
+    /// Nodes without a position started outside any element of the
+    /// fragment, after the given number of `closed` tags; they are only
+    /// valid in an OsmChange delete block
+    pub(crate) positionless: Vec<(usize, OsmParseError)>,
+    /// Elements still open at the end of the fragment, outermost first
+    pub(crate) open: Vec<String>,
+    pub(crate) end: Position,
+}
+
+/// Counts the lines consumed from the wrapped input
+pub(crate) struct LineCounter<R> {
+    inner: R,
+    lines: u64,
+}
+
+impl<R: BufRead> XmlParser<BufferedSource<R>> {
+    pub(crate) fn new(input: R) -> Self {
+        let mut reader = Reader::from_reader(LineCounter {
+            inner: input,
+            lines: 0,
+        });
+        // End tags are matched by the element stack, which carries on
+        reader.check_end_names(false);
+
+        Self::with_source(BufferedSource {
+            reader,
+            buf: Vec::new(),
+            start: Position::default(),
+        })
+    }
+}
+
+impl<'a> XmlParser<SliceSource<'a>> {
+    /// Parse a complete document in memory
+    pub(crate) fn from_slice(data: &'a [u8]) -> Self {
+        Self::slice_at(data, Position::default())
+    }
+
+    /// Parse a fragment of a document, in which end tags may be unmatched,
+    /// that starts at `start` in the complete document
+    pub(crate) fn fragment(data: &'a [u8], start: Position) -> Self {
+        let mut parser = Self::slice_at(data, start);
+        parser.stack.fragment = Some(Fragment::default());
+
+        parser
+    }
+
+    fn slice_at(data: &'a [u8], start: Position) -> Self {
+        let mut reader = Reader::from_reader(data);
+        reader.check_end_names(false);
+
+        Self::with_source(SliceSource {
+            reader,
+            data,
+            start,
+            lines: 0,
+            counted: 0,
+        })
+    }
+}
+
+impl<S: XmlSource> XmlParser<S> {
+    fn with_source(source: S) -> Self {
+        Self {
+            source,
+            current: None,
+            current_depth: 0,
+            action: None,
+            stack: ElementStack::default(),
+        }
+    }
+
+    /// How the fragment parsed so far relates to the rest of the document
+    pub(crate) fn into_fragment(mut self) -> Fragment {
+        let end = self.position();
+        let mut fragment = self.stack.fragment.take().unwrap_or_default();
+        fragment.end = end;
+        fragment.open = (0..self.stack.depth())
+            .map(|depth| String::from_utf8_lossy(self.stack.name(depth)).into_owned())
+            .collect();
+
+        fragment
+    }
+
+    /// Position in the complete document of the last event read
+    pub(crate) fn position(&self) -> Position {
+        self.source.position()
+    }
+
+    pub(crate) fn next_element(
+        &mut self,
+        statistics: &mut Statistics,
+    ) -> Result<Option<Change>, OsmParseError> {
+        self.read_element(statistics)
+            .map_err(|e| e.at(self.position()))
+    }
+
+    fn read_element(
+        &mut self,
+        statistics: &mut Statistics,
+    ) -> Result<Option<Change>, OsmParseError> {
+        loop {
+            let (event, position) = self.source.read_event()?;
+            match event {
+                Event::Eof => {
+                    self.stack.finish(position, statistics);
+
+                    return Ok(None);
+                }
+
+                Event::Start(bytes) => {
+                    let name = bytes.name();
+                    let depth = self.stack.depth();
+                    self.stack.push(name.local_name().as_ref(), statistics);
+
+                    match (
+                        OsmTag::try_from(name.local_name().as_ref()),
+                        &mut self.current,
+                    ) {
+                        (Ok(tag), None) => {
+                            let action = self.action.map(|(action, _)| action);
+                            self.current =
+                                Some(self.stack.start_element(
+                                    tag, &bytes, action, depth, position, statistics,
+                                )?);
+                            self.current_depth = self.stack.depth();
+                        }
+                        (Ok(tag), Some(parent)) => {
+                            statistics.issues.push(nested(tag, parent, position))
+                        }
+                        (Err(_), Some(parent)) if depth == self.current_depth => {
+                            add_child(parent, &bytes)?
+                        }
+                        (Err(_), None) => {
+                            if let Ok(action) = Action::try_from(name.local_name().as_ref()) {
+                                self.action = Some((action, self.stack.depth()));
+                            }
+                        }
+                        (Err(_), _) => (),
+                    }
+                }
+
+                Event::Empty(bytes) => {
+                    let name = bytes.name();
+                    let depth = self.stack.depth();
+                    self.stack.empty(name.local_name().as_ref(), statistics);
+
+                    match (
+                        OsmTag::try_from(name.local_name().as_ref()),
+                        &mut self.current,
+                    ) {
+                        (Ok(tag), None) => {
+                            let action = self.action.map(|(action, _)| action);
+                            let element = self
+                                .stack
+                                .start_element(tag, &bytes, action, depth, position, statistics)?;
+                            return Ok(Some(Change { action, element }));
+                        }
+                        (Ok(tag), Some(parent)) => {
+                            statistics.issues.push(nested(tag, parent, position))
+                        }
+                        (Err(_), Some(parent)) if self.stack.depth() == self.current_depth => {
+                            add_child(parent, &bytes)?
+                        }
+                        (Err(_), _) => (),
+                    }
+                }
+
+                Event::End(bytes) => {
+                    let name = bytes.name();
+                    self.stack
+                        .pop(name.local_name().as_ref(), position, statistics);
+
+                    // Also when closed by the end tag of an element around it
+                    let mut change = None;
+                    if self.stack.depth() < self.current_depth {
+                        self.current_depth = 0;
+                        let action = self.action.map(|(action, _)| action);
+                        change = self
+                            .current
+                            .take()
+                            .map(|element| Change { action, element });
+                    }
+                    if let Some((_, depth)) = self.action {
+                        if self.stack.depth() < depth {
+                            self.action = None;
+                        }
+                    }
+                    if change.is_some() {
+                        return Ok(change);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+    }
+}
+
+impl ElementStack {
+    fn parent(&self) -> Option<&[u8]> {
+        self.depth().checked_sub(1).map(|depth| self.name(depth))
+    }
+
+    /// Count the start of `name`, under the innermost open element
+    fn register_start(&mut self, name: &[u8], statistics: &mut Statistics) {
+        statistics.register_tag(true, name, self.parent());
+
+        // The parent is outside the fragment, so it is not known yet
+        if let (None, Some(fragment)) = (self.ends.last(), self.fragment.as_mut()) {
+            let closed = fragment.closed.len();
+            if fragment.unresolved.len() <= closed {
+                fragment.unresolved.resize_with(closed + 1, HashMap::new);
+            }
+            let counts = &mut fragment.unresolved[closed];
+            match counts.get_mut(String::from_utf8_lossy(name).as_ref()) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(String::from_utf8_lossy(name).into_owned(), 1);
+                }
+            }
+        }
+    }
+
+    /// Start parsing an element at `depth`, counting it under the OsmChange
+    /// `action` it is in
+    fn start_element(
+        &mut self,
+        tag: OsmTag,
+        bytes: &BytesStart,
+        action: Option<Action>,
+        depth: usize,
+        position: Position,
+        statistics: &mut Statistics,
+    ) -> Result<Element, OsmParseError> {
+        if let Some(action) = action {
+            statistics.register_change(action, tag, 1);
+        }
+
+        match start_element(tag, bytes, action == Some(Action::Delete)) {
+            // Whether the element is in a delete block is not known yet
+            Err(
+                error @ OsmParseError::MissingAttribute {
+                    name: "lat" | "lon",
+                    ..
+                },
+            ) if depth == 0 && self.fragment.is_some() => {
+                if let Some(fragment) = self.fragment.as_mut() {
+                    let closed = fragment.closed.len();
+                    fragment.positionless.push((closed, error.at(position)));
+                }
+                start_element(tag, bytes, true)
+            }
+            result => result,
+        }
+    }
+
+    fn push(&mut self, name: &[u8], statistics: &mut Statistics) {
+        self.register_start(name, statistics);
+        self.names.extend_from_slice(name);
+        self.ends.push(self.names.len());
+    }
+
+    fn empty(&mut self, name: &[u8], statistics: &mut Statistics) {
+        self.register_start(name, statistics);
+        statistics.register_tag(false, name, None);
+    }
+
+    /// Close the innermost element named `name`, along with any elements
+    /// left open inside it
+    fn pop(&mut self, name: &[u8], position: Position, statistics: &mut Statistics) {
+        statistics.register_tag(false, name, None);
+
+        match (0..self.depth())
+            .rev()
+            .find(|&depth| self.name(depth) == name)
+        {
+            Some(depth) => {
+                self.unwind(depth + 1, name, position, statistics);
+                self.pop_name();
+            }
+            // Left to be matched against the elements opened before
+            None if self.depth() == 0 && self.fragment.is_some() => {
+                if let Some(fragment) = self.fragment.as_mut() {
+                    let found = String::from_utf8_lossy(name).into_owned();
+                    fragment.closed.push((found, position));
+                }
+            }
+            None => statistics.issues.push(StructureIssue::UnexpectedEnd {
+                found: String::from_utf8_lossy(name).into_owned(),
+                position,
+            }),
+        }
+    }
+
+    /// Report every element deeper than `depth` as left open by `found`
+    fn unwind(
+        &mut self,
+        depth: usize,
+        found: &[u8],
+        position: Position,
+        statistics: &mut Statistics,
+    ) {
+        while self.depth() > depth {
+            let open = self.pop_name();
+            statistics.issues.push(StructureIssue::Mismatch {
+                open,
+                found: String::from_utf8_lossy(found).into_owned(),
+                position,
+            });
+        }
+    }
+
+    /// Report the elements still open at the end of a complete document
+    fn finish(&mut self, position: Position, statistics: &mut Statistics) {
+        if self.fragment.is_none() {
+            while self.depth() > 0 {
+                let open = self.pop_name();
+                statistics
+                    .issues
+                    .push(StructureIssue::Unclosed { open, position });
+            }
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Name of the element open at `depth`, counting from zero
+    fn name(&self, depth: usize) -> &[u8] {
+        let start = match depth {
+            0 => 0,
+            depth => self.ends[depth - 1],
+        };
+
+        &self.names[start..self.ends[depth]]
+    }
+
+    fn pop_name(&mut self) -> String {
+        let name = String::from_utf8_lossy(self.name(self.depth() - 1)).into_owned();
+        self.ends.pop();
+        self.names.truncate(self.ends.last().copied().unwrap_or(0));
+
+        name
+    }
+}
+
+impl<R: BufRead> XmlSource for BufferedSource<R> {
+    fn read_event(&mut self) -> Result<(Event<'_>, Position), quick_xml::Error> {
+        self.buf.clear();
+        let event = self.reader.read_event_into(&mut self.buf)?;
+        let position = Position {
+            offset: self.start.offset + self.reader.buffer_position() as u64,
+            line: Some(self.start.line.unwrap_or(1) + self.reader.get_ref().lines),
+        };
+
+        Ok((event, position))
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            offset: self.start.offset + self.reader.buffer_position() as u64,
+            line: Some(self.start.line.unwrap_or(1) + self.reader.get_ref().lines),
+        }
+    }
+}
+
+impl XmlSource for SliceSource<'_> {
+    fn read_event(&mut self) -> Result<(Event<'_>, Position), quick_xml::Error> {
+        let event = self.reader.read_event();
+        // Lines are counted up to the last event, also after an error
+        let end = self.reader.buffer_position().min(self.data.len());
+        self.lines += count_lines(&self.data[self.counted..end]);
+        self.counted = end;
+
+        Ok((event?, self.position()))
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            offset: self.start.offset + self.counted as u64,
+            line: Some(self.start.line.unwrap_or(1) + self.lines),
+        }
+    }
+}
+
+fn nested(element: OsmTag, parent: &Element, position: Position) -> StructureIssue {
+    StructureIssue::Nested {
+        element,
+        parent: parent.tag(),
+        position,
+    }
+}
+
+impl<R: BufRead> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.lines += count_lines(&buf[..n]);
+
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for LineCounter<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Consumed bytes are still buffered, so this does not read
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.lines += count_lines(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+pub(crate) fn count_lines(data: &[u8]) -> u64 {
+    data.iter().filter(|&&b| b == b'\n').count() as u64
+}
+
+/// Parse the attributes of an element; with `deleted`, the element is in
+/// an OsmChange delete block
+fn start_element(tag: OsmTag, bytes: &BytesStart, deleted: bool) -> Result<Element, OsmParseError> {
+    let mut id = None;
+    let (mut lat, mut lon) = (None, None);
+    let mut meta = Meta::default();
+
+    for attribute in bytes.attributes() {
+        let attribute = attribute?;
+        let key = attribute.key.local_name();
+        let value = attribute.unescape_value()?;
+        let bad_attribute = || bad(tag, &String::from_utf8_lossy(key.as_ref()), &value);
+
+        match key.as_ref() {
+            b"id" => id = Some(value.parse().map_err(|_| bad_attribute())?),
+            b"lat" => lat = Some(parse_nanodegrees(&value, 90).ok_or_else(bad_attribute)?),
+            b"lon" => lon = Some(parse_nanodegrees(&value, 180).ok_or_else(bad_attribute)?),
+            b"version" => meta.version = Some(value.parse().map_err(|_| bad_attribute())?),
+            b"timestamp" => {
+                meta.timestamp = Some(parse_timestamp(&value).ok_or_else(bad_attribute)?)
+            }
+            b"changeset" => meta.changeset = Some(value.parse().map_err(|_| bad_attribute())?),
+            b"uid" => meta.uid = Some(value.parse().map_err(|_| bad_attribute())?),
+            b"user" => meta.user = Some(value.to_string()),
+            b"visible" => meta.visible = parse_visible(&value).ok_or_else(bad_attribute)?,
+            _ => (),
+        }
+    }
+
+    let id = id.ok_or_else(|| missing(tag, "id"))?;
+
+    Ok(match tag {
+        OsmTag::Node => {
+            // Deleted nodes are allowed to lose their position
+            let (lat, lon) = match (lat, lon, meta.visible && !deleted) {
+                (Some(lat), Some(lon), _) => (lat, lon),
+                (None, _, true) => return Err(missing(tag, "lat")),
+                (_, None, true) => return Err(missing(tag, "lon")),
+                (lat, lon, false) => (lat.unwrap_or_default(), lon.unwrap_or_default()),
+            };
+
+            Element::Node(Node {
+                id,
+                lat,
+                lon,
+                meta,
+                ..Node::default()
+            })
+        }
+        OsmTag::Way => Element::Way(Way {
+            id,
+            meta,
+            ..Way::default()
+        }),
+        OsmTag::Relation => Element::Relation(Relation {
+            id,
+            meta,
+            ..Relation::default()
+        }),
+    })
+}
+
+/// Decode a child element such as `<tag>`, `<nd>` or `<member>` into its parent
+fn add_child(parent: &mut Element, bytes: &BytesStart) -> Result<(), OsmParseError> {
+    match (bytes.name().local_name().as_ref(), parent) {
+        (b"tag", parent) => {
+            let (mut key, mut value) = (None, None);
+            for attribute in bytes.attributes() {
+                let attribute = attribute?;
+                match attribute.key.local_name().as_ref() {
+                    b"k" => key = Some(attribute.unescape_value()?.into_owned()),
+                    b"v" => value = Some(attribute.unescape_value()?.into_owned()),
+                    _ => (),
+                }
+            }
+
+            let key = key.ok_or_else(|| missing(parent.tag(), "k"))?;
+            let value = value.ok_or_else(|| missing(parent.tag(), "v"))?;
+            parent.tags_mut().push((key, value));
+        }
+
+        (b"nd", Element::Way(way)) => {
+            let node_ref = required_id(bytes, OsmTag::Way, "ref")?;
+            way.refs.push(node_ref);
+        }
+
+        (b"member", Element::Relation(relation)) => {
+            let (mut member_type, mut role) = (None, String::new());
+            for attribute in bytes.attributes() {
+                let attribute = attribute?;
+                match attribute.key.local_name().as_ref() {
+                    b"type" => {
+                        member_type =
+                            Some(OsmTag::try_from(attribute.value.as_ref()).map_err(|_| {
+                                bad(
+                                    OsmTag::Relation,
+                                    "type",
+                                    &String::from_utf8_lossy(&attribute.value),
+                                )
+                            })?)
+                    }
+                    b"role" => role = attribute.unescape_value()?.into_owned(),
+                    _ => (),
+                }
+            }
+
+            let member_type = member_type.ok_or_else(|| missing(OsmTag::Relation, "type"))?;
+            let member_ref = required_id(bytes, OsmTag::Relation, "ref")?;
+            relation.members.push(Member {
+                member_type,
+                member_ref,
+                role,
+            });
+        }
+
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Parse the id valued attribute `name` of a child element
+fn required_id(
+    bytes: &BytesStart,
+    element: OsmTag,
+    name: &'static str,
+) -> Result<i64, OsmParseError> {
+    for attribute in bytes.attributes() {
+        let attribute = attribute?;
+        if attribute.key.local_name().as_ref() == name.as_bytes() {
+            let value = attribute.unescape_value()?;
+            return value.parse().map_err(|_| bad(element, name, &value));
+        }
+    }
+
+    Err(missing(element, name))
+}
+
+fn missing(element: OsmTag, name: &'static str) -> OsmParseError {
+    OsmParseError::MissingAttribute {
+        element,
+        name,
+        position: Position::default(),
+    }
+}
+
+fn bad(element: OsmTag, name: &str, value: &str) -> OsmParseError {
+    OsmParseError::BadAttribute {
+        element,
+        name: name.to_string(),
+        value: value.to_string(),
+        position: Position::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        Action, Element, OsmParseError, OsmReader, OsmSliceReader, OsmTag, Position, Statistics,
+        StructureIssue,
+    };
+
+    /// The first error reading `document`
+    fn error(document: &str) -> OsmParseError {
+        let mut reader = OsmReader::new(document.as_bytes());
+        let error = reader.elements().find_map(Result::err);
+
+        error.expect("no error")
+    }
+
+    #[test]
+    fn bad_attributes() {
+        let cases = [
+            ("lat", "abc", "<node id=\"1\" lat=\"abc\" lon=\"2\"/>"),
+            ("lat", "90.5", "<node id=\"1\" lat=\"90.5\" lon=\"2\"/>"),
+            (
+                "version",
+                "-1",
+                "<node id=\"1\" lat=\"1\" lon=\"2\" version=\"-1\"/>",
+            ),
+            (
+                "timestamp",
+                "2021-02-31T00:00:00Z",
+                "<node id=\"1\" lat=\"1\" lon=\"2\" timestamp=\"2021-02-31T00:00:00Z\"></node>",
+            ),
+        ];
+        for (attribute, bad_value, node) in cases {
+            let prefix = "<osm>\n<node id=\"0\" lat=\"0\" lon=\"0\"/>\n";
+            let document = format!("{}{}\n</osm>\n", prefix, node);
+            // Errors are reported just after the start tag
+            let start_tag = node.find('>').unwrap() + 1;
+
+            match error(&document) {
+                OsmParseError::BadAttribute {
+                    element,
+                    name,
+                    value,
+                    position,
+                } => {
+                    assert_eq!(element, OsmTag::Node);
+                    assert_eq!((name.as_str(), value.as_str()), (attribute, bad_value));
+                    assert_eq!(
+                        position,
+                        Position {
+                            offset: (prefix.len() + start_tag) as u64,
+                            line: Some(3),
+                        },
+                        "{}",
+                        node
+                    );
+                }
+                other => panic!("{}: {:?}", node, other),
+            }
+        }
+    }
+
+    /// Elements and statistics of `document`, checking that the buffered
+    /// and in-place readers agree
+    fn read(document: &str) -> (Vec<Element>, Statistics) {
+        let mut reader = OsmReader::new(document.as_bytes());
+        let elements: Vec<_> = reader.elements().collect::<Result<_, _>>().unwrap();
+        let statistics = reader.into_statistics();
+
+        let mut slice_reader = OsmSliceReader::new(document.as_bytes());
+        let slice_elements: Vec<_> = slice_reader.elements().collect::<Result<_, _>>().unwrap();
+        assert_eq!(slice_elements, elements);
+        assert_eq!(slice_reader.into_statistics(), statistics);
+
+        (elements, statistics)
+    }
+
+    /// Position just after the first occurrence of `text` in `document`
+    fn after(document: &str, text: &str) -> Position {
+        let end = document.find(text).unwrap() + text.len();
+        Position {
+            offset: end as u64,
+            line: Some(1 + document[..end].matches('\n').count() as u64),
+        }
+    }
+
+    #[test]
+    fn mismatch() {
+        let document = "<osm>\n<extra>\n<other>\n</osm>\n";
+        let (_, statistics) = read(document);
+        let position = after(document, "</osm>");
+        assert_eq!(
+            statistics.issues,
+            [
+                StructureIssue::Mismatch {
+                    open: "other".to_string(),
+                    found: "osm".to_string(),
+                    position,
+                },
+                StructureIssue::Mismatch {
+                    open: "extra".to_string(),
+                    found: "osm".to_string(),
+                    position,
+                },
+            ]
+        );
+        assert_eq!(position.line, Some(4));
+    }
+
+    #[test]
+    fn unexpected_end() {
+        let document = "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n</bogus>\n</osm>\n";
+        let (elements, statistics) = read(document);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(
+            statistics.issues,
+            [StructureIssue::UnexpectedEnd {
+                found: "bogus".to_string(),
+                position: after(document, "</bogus>"),
+            }]
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let document = "<osm>\n<way id=\"1\">\n<nd ref=\"2\"/>\n\
+                        <node id=\"2\" lat=\"1\" lon=\"2\"/>\n\
+                        <relation id=\"3\"></relation>\n</way>\n</osm>\n";
+        let (elements, statistics) = read(document);
+        // The inner elements are skipped
+        assert_eq!(elements.len(), 1);
+        assert!(matches!(&elements[0], Element::Way(way) if way.refs == [2]));
+        assert_eq!(
+            statistics.issues,
+            [
+                StructureIssue::Nested {
+                    element: OsmTag::Node,
+                    parent: OsmTag::Way,
+                    position: after(document, "lon=\"2\"/>"),
+                },
+                StructureIssue::Nested {
+                    element: OsmTag::Relation,
+                    parent: OsmTag::Way,
+                    position: after(document, "<relation id=\"3\">"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed() {
+        let document = "<osm>\n<way id=\"1\">\n<nd ref=\"2\"/>\n";
+        let (elements, statistics) = read(document);
+        // The incomplete way is not yielded
+        assert!(elements.is_empty());
+        let position = Position {
+            offset: document.len() as u64,
+            line: Some(4),
+        };
+        assert_eq!(
+            statistics.issues,
+            [
+                StructureIssue::Unclosed {
+                    open: "way".to_string(),
+                    position,
+                },
+                StructureIssue::Unclosed {
+                    open: "osm".to_string(),
+                    position,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn balanced() {
+        let document =
+            "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"><tag k=\"a\" v=\"b\"/></node>\n</osm>\n";
+        let (elements, statistics) = read(document);
+        assert_eq!(elements.len(), 1);
+        assert!(statistics.issues.is_empty());
+    }
+
+    #[test]
+    fn change_actions() {
+        let document = r#"<osmChange version="0.6">
+            <create>
+              <node id="-1" lat="1" lon="2"/>
+              <way id="-2"><nd ref="-1"/></way>
+            </create>
+            <modify>
+              <node id="3" version="2" lat="1" lon="2"><tag k="a" v="b"/></node>
+            </modify>
+            <delete>
+              <node id="4" version="3"/>
+              <node id="5" version="3" visible="false"/>
+              <relation id="6"/>
+            </delete>
+            <delete if-unused="true"><way id="7"/></delete>
+            </osmChange>"#;
+        let mut reader = OsmReader::new(document.as_bytes());
+        let changes: Vec<_> = reader
+            .changes()
+            .map(|change| {
+                let change = change.unwrap();
+                (change.action, change.element.tag(), change.element.id())
+            })
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                (Some(Action::Create), OsmTag::Node, -1),
+                (Some(Action::Create), OsmTag::Way, -2),
+                (Some(Action::Modify), OsmTag::Node, 3),
+                (Some(Action::Delete), OsmTag::Node, 4),
+                (Some(Action::Delete), OsmTag::Node, 5),
+                (Some(Action::Delete), OsmTag::Relation, 6),
+                (Some(Action::Delete), OsmTag::Way, 7),
+            ]
+        );
+
+        let expected: HashMap<_, HashMap<_, _>> = [
+            (
+                Action::Create,
+                [(OsmTag::Node, 1), (OsmTag::Way, 1)].into_iter().collect(),
+            ),
+            (Action::Modify, [(OsmTag::Node, 1)].into_iter().collect()),
+            (
+                Action::Delete,
+                [(OsmTag::Node, 2), (OsmTag::Way, 1), (OsmTag::Relation, 1)]
+                    .into_iter()
+                    .collect(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(reader.statistics().changes, expected);
+    }
+
+    #[test]
+    fn change_outside_action() {
+        let document = r#"<osm><node id="1" lat="1" lon="2"/></osm>"#;
+        let mut reader = OsmReader::new(document.as_bytes());
+        let change = reader.changes().next().unwrap().unwrap();
+        assert_eq!(change.action, None);
+        assert!(reader.statistics().changes.is_empty());
+    }
+
+    #[test]
+    fn positionless_node_outside_delete() {
+        let cases = [
+            r#"<osmChange><modify><node id="1" version="2"/></modify></osmChange>"#,
+            r#"<osmChange><create><node id="1" lat="1"/></create></osmChange>"#,
+            // The delete block is closed before the node
+            r#"<osmChange><delete></delete><node id="1"/></osmChange>"#,
+        ];
+        for document in cases {
+            assert!(
+                matches!(error(document), OsmParseError::MissingAttribute { .. }),
+                "{}",
+                document
+            );
+        }
+    }
+
+    fn tags(element: &Element) -> Vec<(&str, &str)> {
+        element
+            .tags()
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn tag_entities() {
+        let document = r#"<osm><node id="1" lat="1" lon="2">
+            <tag k="name:&quot;x&quot;" v="Fish &amp; Chips &lt;3&gt;"/>
+            <tag k='a' v='it&apos;s&#10;&#x41;'/>
+            <tag k="" v=""/>
+            </node></osm>"#;
+        let (elements, _) = read(document);
+        assert_eq!(
+            tags(&elements[0]),
+            [
+                ("name:\"x\"", "Fish & Chips <3>"),
+                ("a", "it's\nA"),
+                ("", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_errors() {
+        let cases = [
+            (
+                r#"<osm><node id="1" lat="1" lon="2"><tag v="b"/></node></osm>"#,
+                OsmTag::Node,
+                "k",
+            ),
+            (
+                r#"<osm><way id="1"><tag k="a"/></way></osm>"#,
+                OsmTag::Way,
+                "v",
+            ),
+        ];
+        for (document, expected_element, expected_name) in cases {
+            match error(document) {
+                OsmParseError::MissingAttribute { element, name, .. } => {
+                    assert_eq!((element, name), (expected_element, expected_name));
+                }
+                other => panic!("{}: {:?}", document, other),
+            }
+        }
+
+        let document = r#"<osm><node id="1" lat="1" lon="2"><tag k="a" v="&bogus;"/></node></osm>"#;
+        assert!(matches!(error(document), OsmParseError::Xml { .. }));
+    }
+
+    #[test]
+    fn way_refs() {
+        let document = r#"<osm><way id="1">
+            <nd ref="3"/>
+            <extra><nd ref="9"/></extra>
+            <node id="5" lat="0" lon="0"><nd ref="8"/></node>
+            <nd ref="1"></nd>
+            <nd ref="2"/>
+            </way></osm>"#;
+        let (elements, _) = read(document);
+        match &elements[..] {
+            [Element::Way(way)] => assert_eq!(way.refs, [3, 1, 2]),
+            other => panic!("{:?}", other),
+        }
+
+        let document = r#"<osm><way id="1"><nd ref="1"/><nd ref="x"/></way></osm>"#;
+        match error(document) {
+            OsmParseError::BadAttribute {
+                element,
+                name,
+                value,
+                ..
+            } => assert_eq!(
+                (element, name.as_str(), value.as_str()),
+                (OsmTag::Way, "ref", "x")
+            ),
+            other => panic!("{:?}", other),
+        }
+
+        let document = r#"<osm><way id="1"><nd/></way></osm>"#;
+        assert!(matches!(
+            error(document),
+            OsmParseError::MissingAttribute {
+                element: OsmTag::Way,
+                name: "ref",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn relation_members() {
+        let document = r#"<osm><relation id="1">
+            <member type="way" ref="7" role="outer"/>
+            <member type="Node" ref="-2" role="a &amp; b"/>
+            <member type="relation" ref="3"/>
+            </relation></osm>"#;
+        let (elements, _) = read(document);
+        let members = match &elements[..] {
+            [Element::Relation(relation)] => &relation.members,
+            other => panic!("{:?}", other),
+        };
+        let members: Vec<_> = members
+            .iter()
+            .map(|member| (member.member_type, member.member_ref, member.role.as_str()))
+            .collect();
+        assert_eq!(
+            members,
+            [
+                (OsmTag::Way, 7, "outer"),
+                (OsmTag::Node, -2, "a & b"),
+                (OsmTag::Relation, 3, ""),
+            ]
+        );
+
+        let document = r#"<osm><relation id="1"><member type="area" ref="1"/></relation></osm>"#;
+        match error(document) {
+            OsmParseError::BadAttribute {
+                element,
+                name,
+                value,
+                ..
+            } => assert_eq!(
+                (element, name.as_str(), value.as_str()),
+                (OsmTag::Relation, "type", "area")
+            ),
+            other => panic!("{:?}", other),
+        }
+
+        for (document, expected_name) in [
+            (
+                r#"<osm><relation id="1"><member ref="1"/></relation></osm>"#,
+                "type",
+            ),
+            (
+                r#"<osm><relation id="1"><member type="way"/></relation></osm>"#,
+                "ref",
+            ),
+        ] {
+            match error(document) {
+                OsmParseError::MissingAttribute { element, name, .. } => {
+                    assert_eq!((element, name), (OsmTag::Relation, expected_name));
+                }
+                other => panic!("{}: {:?}", document, other),
+            }
+        }
+    }
+}
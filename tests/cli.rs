@@ -0,0 +1,93 @@
+//! Exit codes and report layout of the command line tool
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use serde_json::Value;
+
+/// Write `content` to a file of the given name in a directory of its own
+fn input(name: &str, content: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("osm-parse-cli-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join(name);
+    fs::write(&path, content).unwrap();
+
+    path
+}
+
+fn run(args: &[&str], file: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_osm-parse"))
+        .args(args)
+        .arg(file)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn strict_exits_on_structure_issues() {
+    let file = input(
+        "unbalanced.osm",
+        "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n</bogus>\n</osm>\n",
+    );
+
+    let default = run(&[], &file);
+    assert_eq!(default.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&default.stderr).contains("bogus"));
+
+    let strict = run(&["--strict"], &file);
+    assert_eq!(strict.status.code(), Some(7));
+}
+
+#[test]
+fn strict_accepts_balanced_documents() {
+    let file = input(
+        "balanced.osm",
+        "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n</osm>\n",
+    );
+
+    assert_eq!(run(&["--strict"], &file).status.code(), Some(0));
+}
+
+/// The JSON report of the tool run on `file`
+fn json_report(args: &[&str], file: &Path) -> Value {
+    let output = run(
+        &[&["--output", "json", "--no-progress"], args].concat(),
+        file,
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn json_layout_is_independent_of_glob_matches() {
+    let file = input(
+        "single.osm",
+        "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n</osm>\n",
+    );
+
+    let report = json_report(&[], &file);
+    assert!(report.get("files").is_none());
+
+    // A pattern matching only this file
+    let pattern = file.with_file_name("singl?.osm");
+    let report = json_report(&[], &pattern);
+    assert_eq!(report["files"].as_array().map(Vec::len), Some(1));
+    assert_eq!(report["total"]["input"]["files"], 1);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn json_records_memory_mapping() {
+    let file = input(
+        "mapped.osm",
+        "<osm>\n<node id=\"1\" lat=\"1\" lon=\"2\"/>\n</osm>\n",
+    );
+
+    assert_eq!(json_report(&[], &file)["input"]["mmap"], true);
+    assert_eq!(json_report(&["--no-mmap"], &file)["input"]["mmap"], false);
+    assert_eq!(json_report(&["-j", "2"], &file)["input"]["mmap"], false);
+}